@@ -0,0 +1,297 @@
+use crate::*;
+use crate::validate::{Validate, FieldError};
+
+use actix_web::{
+    HttpRequest, ResponseError, middleware,
+    http::StatusCode,
+    error::{JsonPayloadError, PathError, QueryPayloadError}
+};
+
+#[derive(Debug, Display)]
+pub enum ApiError {
+    #[display("note not found")]
+    NoteNotFound,
+    #[display("not found")]
+    NotFound,
+    #[display("{_0}")]
+    BadRequest(String),
+    #[display("{_0}")]
+    Conflict(String),
+    #[display("{_0}")]
+    Unauthorized(&'static str),
+    #[display("{_0}")]
+    Forbidden(&'static str),
+    #[display("request body is too large, the limit is {_0} bytes")]
+    PayloadTooLarge(usize),
+    #[display("invalid request")]
+    Validation(Vec::<FieldError>),
+    #[display("too many requests, try again in {_0} seconds")]
+    TooManyRequests(u64),
+    #[display("{_0}")]
+    QuotaExceeded(&'static str),
+    #[display("{_0}")]
+    Internal(String)
+}
+
+impl From::<rusqlite::Error> for ApiError {
+    #[inline]
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Internal(format!("database error: {e}"))
+    }
+}
+
+impl ApiError {
+    /// Maps a unique constraint violation to a 409 saying `what` named `name` already exists.
+    pub fn from_unique(e: rusqlite::Error, what: &str, name: &str) -> Self {
+        match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::ConstraintViolation) => Self::Conflict(format!("{what} {name:?} already exists")),
+            _ => e.into()
+        }
+    }
+}
+
+impl From::<r2d2::Error> for ApiError {
+    #[inline]
+    fn from(e: r2d2::Error) -> Self {
+        Self::Internal(format!("database error: {e}"))
+    }
+}
+
+impl ApiError {
+    #[inline]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NoteNotFound | Self::NotFound => "not_found",
+            Self::BadRequest(..) => "bad_request",
+            Self::Conflict(..) => "conflict",
+            Self::Unauthorized(..) => "unauthorized",
+            Self::Forbidden(..) => "forbidden",
+            Self::PayloadTooLarge(..) => "payload_too_large",
+            Self::Validation(..) => "validation",
+            Self::TooManyRequests(..) => "too_many_requests",
+            Self::QuotaExceeded(..) => "quota_exceeded",
+            Self::Internal(..) => "internal"
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    #[inline]
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NoteNotFound | Self::NotFound => StatusCode::NOT_FOUND,
+            Self::BadRequest(..) | Self::Validation(..) => StatusCode::BAD_REQUEST,
+            Self::Conflict(..) => StatusCode::CONFLICT,
+            Self::Unauthorized(..) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(..) => StatusCode::FORBIDDEN,
+            Self::PayloadTooLarge(..) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            Self::QuotaExceeded(..) => StatusCode::INSUFFICIENT_STORAGE,
+            Self::Internal(..) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    #[inline]
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({
+            "error": self.kind(),
+            "message": self.to_string()
+        });
+        if let Self::Validation(fields) = self {
+            body["fields"] = json!(fields)
+        }
+        let mut resp = HttpResponse::build(self.status_code());
+        if let Self::TooManyRequests(retry_after) = self {
+            resp.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        resp.json(body)
+    }
+}
+
+pub type ApiResult = Result::<HttpResponse, ApiError>;
+
+#[inline]
+pub fn json_config(config: &Config) -> web::JsonConfig {
+    web::JsonConfig::default().limit(config.max_payload_size).error_handler(|err: JsonPayloadError, _| {
+        match err {
+            JsonPayloadError::Overflow { limit } |
+            JsonPayloadError::OverflowKnownLength { limit, .. } => ApiError::PayloadTooLarge(limit),
+            _ => ApiError::BadRequest(err.to_string())
+        }.into()
+    })
+}
+
+#[inline]
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|err: PathError, _| {
+        ApiError::BadRequest(err.to_string()).into()
+    })
+}
+
+#[inline]
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err: QueryPayloadError, _| {
+        ApiError::BadRequest(err.to_string()).into()
+    })
+}
+
+#[inline(always)]
+fn note_location(uuid: &Uuid) -> String {
+    format!("/api/v1/notes/{uuid}")
+}
+
+/// Filters of the note listing, `tags` is a comma separated list of tags a note must all have,
+/// `notebook` is either a notebook UUID or `none` for notes outside of any notebook,
+/// `overdue` keeps only notes past their due date that aren't in a done status.
+#[derive(Deserialize)]
+pub struct NotesQuery {
+    tags: Option::<String>,
+    notebook: Option::<String>,
+    status: Option::<Status>,
+    #[serde(default)]
+    overdue: bool
+}
+
+impl NotesQuery {
+    pub fn filter(&self, workflow: &Workflow) -> Result::<impl Fn(&Note) -> bool + '_, ApiError> {
+        let notebook = match self.notebook.as_deref() {
+            None => None,
+            Some("none") => Some(None),
+            Some(uuid) => Some(Some(Uuid::parse_str(uuid).map_err(|e| {
+                ApiError::BadRequest(format!("invalid notebook: {e}"))
+            })?))
+        };
+        let tags = self.tags.as_deref().map(|tags| {
+            tags.split(',').map(str::trim).filter(|tag| !tag.is_empty()).collect::<Vec::<_>>()
+        }).unwrap_or_default();
+        let now = unix_now();
+        let done = workflow.statuses.iter().filter(|def| def.done).map(|def| def.name.clone()).collect::<Vec::<_>>();
+        Ok(move |note: &Note| {
+            notebook.is_none_or(|notebook| note.notebook == notebook)
+            && self.status.as_ref().is_none_or(|status| note.status == *status)
+            && tags.iter().all(|tag| note.tags.iter().any(|t| **t == **tag))
+            && (!self.overdue || note.due_at.is_some_and(|due_at| due_at < now) && !done.contains(&note.status))
+        })
+    }
+}
+
+#[inline]
+#[get("/notes")]
+async fn list_notes(state: Data::<Server>, user: User, query: web::Query::<NotesQuery>) -> ApiResult {
+    let filter = query.filter(&state.workflow.read().unwrap())?;
+    let notes = state.live_notes(user).into_iter().filter(|note| filter(note)).collect::<Vec::<_>>();
+    Ok(HttpResponse::Ok().json(notes))
+}
+
+#[inline]
+#[post("/notes")]
+async fn create_note(state: Data::<Server>, user: User, query: web::Query::<TemplateQuery>, note: Json::<Note>) -> ApiResult {
+    let mut note = note.into_inner();
+    state.check_note_quota()?;
+    note.owner = Some(user.0);
+    templates::apply(&state, user, &query, &mut note, |note| {
+        note.validate(&state.config)?;
+        notebooks::check_notebook(&state, note.owner, note.notebook.as_ref())?;
+        state.workflow.read().unwrap().check_new(&mut note.status)
+    })?;
+    let note = state.create_note(note);
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, note_location(&note.uuid)))
+        .json(note))
+}
+
+#[inline]
+#[get("/notes/{uuid}")]
+async fn get_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    let note = state.get_note_as(user, &uuid, Access::Read)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+#[put("/notes/{uuid}")]
+async fn replace_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, json: Json::<json::NoteBody>) -> ApiResult {
+    let mut body = json.into_inner();
+    body.validate(&state.config)?;
+    let note = state.get_note_as(user, &uuid, Access::Write)?;
+    notebooks::check_move(&state, user, &note, body.notebook.as_ref())?;
+    if body.status.is_empty() {
+        body.status = note.status.clone()
+    }
+    state.check_transition(&uuid, &body.status)?;
+    let note = state.modify_note(&uuid, |note| body.apply(note)).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+#[patch("/notes/{uuid}")]
+async fn patch_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, json: Json::<json::NotePatch>) -> ApiResult {
+    let mut patch = json.into_inner();
+    patch.validate(&state.config)?;
+    let note = state.get_note_as(user, &uuid, Access::Write)?;
+    if let Some(ref notebook) = patch.notebook {
+        notebooks::check_move(&state, user, &note, notebook.as_ref())?
+    }
+    if let Some(ref status) = patch.status {
+        state.check_transition(&uuid, status)?
+    }
+    let note = state.modify_note(&uuid, |note| {
+        if let Some(title) = patch.title { note.title = title }
+        if let Some(status) = patch.status { note.status = status }
+        if let Some(description) = patch.description { note.description = description }
+        if let Some(tags) = patch.tags { note.tags = tags }
+        if let Some(pinned) = patch.pinned { note.pinned = pinned }
+        if let Some(notebook) = patch.notebook { note.notebook = notebook }
+        if let Some(due_at) = patch.due_at { note.due_at = due_at }
+        if let Some(remind_at) = patch.remind_at { note.set_remind_at(remind_at) }
+        if let Some(recurrence) = patch.recurrence { note.recurrence = recurrence }
+        note.mod_time = patch.mod_time.unwrap_or_else(unix_now);
+    }).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+/// Moves the note to the trash, see [`crate::trash`] for getting it back.
+#[inline]
+#[delete("/notes/{uuid}")]
+async fn delete_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_note_as(user, &uuid, Access::Owner)?;
+    let note = state.trash_note(&uuid).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+#[inline]
+async fn not_found(_: HttpRequest) -> ApiResult {
+    Err(ApiError::NotFound)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(auth::pair)
+        .service(users::create_user)
+        .service(web::scope("")
+            .wrap(middleware::from_fn(auth::authenticate))
+            .service(list_notes)
+            .service(create_note)
+            .service(get_note)
+            .service(replace_note)
+            .service(patch_note)
+            .service(delete_note)
+            .configure(trash::config)
+            .configure(history::config)
+            .configure(tags::config)
+            .configure(notebooks::config)
+            .configure(statuses::config)
+            .configure(board::config)
+            .configure(ordering::config)
+            .configure(checklist::config)
+            .configure(links::config)
+            .configure(reminders::config)
+            .configure(templates::config)
+            .configure(attachments::config)
+            .configure(thumbnails::config)
+            .configure(events::config)
+            .configure(collab::config)
+            .configure(sync::config)
+            .configure(auth::config)
+            .configure(users::config)
+            .configure(sharing::config)
+            .configure(share_links::config)
+            .default_service(web::to(not_found)));
+}
@@ -0,0 +1,284 @@
+//! Files attached to notes. Contents are stored once per SHA-256 under [`db::ATTACHMENTS_DIR`], next to the DB,
+//! while `attachments` keeps what note each one belongs to. Attachments go away with their note once it's
+//! removed from the trash, and a file goes away with the last attachment pointing at it.
+//!
+//! Uploads are turned away once the files would take more than `max_storage` together.
+
+use crate::*;
+
+use std::io::Write;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use actix_web::mime;
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use futures_util::StreamExt;
+use actix_web::{HttpRequest, http::header::{ContentDisposition, DispositionParam, DispositionType}};
+
+#[derive(Serialize)]
+pub struct Attachment {
+    id: Uuid,
+    pub note_uuid: Uuid,
+    file_name: Box::<str>,
+    pub content_type: Box::<str>,
+    size: u64,
+    #[serde(skip)]
+    pub hash: Box::<str>,
+    created_at: UnixTimeStamp
+}
+
+const COLUMNS: &str = "id, note_uuid, file_name, content_type, size, hash, created_at";
+
+/// Content types that are safe to display in the browser, everything else is served as a download
+/// so that an uploaded HTML page can't run scripts on our origin.
+const INLINE_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf", "text/plain"];
+
+const NO_ROOM_LEFT: &str = "there's no room left for attachments";
+
+fn from_row(row: &rusqlite::Row) -> Result::<Attachment> {
+    Ok(Attachment {
+        id: Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"),
+        note_uuid: Uuid::parse_str(&row.get::<_, String>(1)?).expect("invalid UUID"),
+        file_name: row.get(2)?,
+        content_type: row.get(3)?,
+        size: row.get(4)?,
+        hash: row.get(5)?,
+        created_at: row.get(6)?
+    })
+}
+
+#[inline(always)]
+pub fn file_path(hash: &str) -> PathBuf {
+    Path::new(db::ATTACHMENTS_DIR).join(hash)
+}
+
+pub fn get_attachment(conn: &Connection, id: &Uuid) -> Result::<Option::<Attachment>> {
+    match conn.query_row(&format!("SELECT {COLUMNS} FROM attachments WHERE id = ?1"), params![id.to_string()], from_row) {
+        Ok(attachment) => Ok(Some(attachment)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+/// Removes the file with the contents `hash` and its thumbnails unless an attachment still points at it.
+/// Holds the write lock meanwhile, so an upload can't link to the file between the check and the removal.
+fn remove_unused_file(conn: &Connection, hash: &str) -> Result::<()> {
+    let tx = rusqlite::Transaction::new_unchecked(conn, rusqlite::TransactionBehavior::Immediate)?;
+    let used = tx.query_row("SELECT EXISTS (SELECT 1 FROM attachments WHERE hash = ?1)", params![hash], |row| row.get::<_, bool>(0))?;
+    if !used {
+        if let Err(e) = fs::remove_file(file_path(hash)) {
+            eprintln!("could not remove attachment file: {hash}: {e}")
+        }
+        thumbnails::remove(hash)
+    }
+    tx.commit()
+}
+
+/// Puts the uploaded file at `tmp_path` in place, unless the same contents are stored already, returns whether it did.
+/// Linking fails rather than replacing a file stored by someone else meanwhile, unlike renaming.
+fn link_file(tmp_path: &Path, hash: &str) -> std::io::Result::<bool> {
+    match fs::hard_link(tmp_path, file_path(hash)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e)
+    }
+}
+
+/// Called by [`Db`] for every note removed for good.
+pub fn remove_note_attachments(conn: &Connection, note_uuid: &str) -> Result::<()> {
+    let hashes = conn.prepare("DELETE FROM attachments WHERE note_uuid = ?1 RETURNING hash")?
+        .query_map(params![note_uuid], |row| row.get::<_, String>(0))?
+        .collect::<Result::<Vec::<_>>>()?;
+    hashes.iter().try_for_each(|hash| remove_unused_file(conn, hash))
+}
+
+fn content_type(field: &actix_multipart::Field, file_name: &str) -> Box::<str> {
+    match field.content_type() {
+        Some(mime) if *mime != mime::APPLICATION_OCTET_STREAM => mime.essence_str().into(),
+        _ => {
+            let ext = Path::new(file_name).extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+            actix_files::file_extension_to_mime(ext).essence_str().into()
+        }
+    }
+}
+
+/// Runs file system work on the blocking thread pool, rather than holding up the worker.
+async fn blocking<R: Send + 'static>(f: impl FnOnce() -> std::io::Result::<R> + Send + 'static) -> Result::<R, ApiError> {
+    web::block(f)
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .map_err(|e| ApiError::Internal(format!("could not store attachment: {e}")))
+}
+
+/// Streams one uploaded file into a temporary file in the attachments directory, returns its hash, its size and
+/// the temporary file, which is for the caller to link and remove. Stops as soon as it gets bigger than `storage_left`.
+async fn store_file(field: &mut actix_multipart::Field, max_size: usize, storage_left: u64) -> Result::<(String, u64, PathBuf), ApiError> {
+    let tmp_path = Path::new(db::ATTACHMENTS_DIR).join(format!(".upload-{uuid}", uuid = Uuid::new_v4()));
+    let mut tmp = Some(blocking({
+        let tmp_path = tmp_path.clone();
+        move || {
+            fs::create_dir_all(db::ATTACHMENTS_DIR)?;
+            File::create(tmp_path)
+        }
+    }).await?);
+
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let written = async {
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            size += chunk.len();
+            if size > max_size { return Err(ApiError::PayloadTooLarge(max_size)) }
+            if size as u64 > storage_left { return Err(ApiError::QuotaExceeded(NO_ROOM_LEFT)) }
+            hasher.update(&chunk);
+            let mut file = tmp.take().expect("file is put back after every chunk");
+            tmp = Some(blocking(move || file.write_all(&chunk).map(|_| file)).await?)
+        }
+        Ok(())
+    }.await;
+    drop(tmp);
+
+    if let Err(e) = written {
+        _ = blocking(move || fs::remove_file(tmp_path)).await;
+        return Err(e)
+    }
+    Ok((format!("{:x}", hasher.finalize()), size as _, tmp_path))
+}
+
+/// How many bytes the stored files take together, each counted once however many attachments point at it.
+fn storage_used(conn: &Connection) -> Result::<u64> {
+    conn.query_row("SELECT COALESCE(SUM(size), 0) FROM (SELECT DISTINCT hash, size FROM attachments)", [], |row| row.get(0))
+}
+
+/// Makes sure `user` has `access` to the note the attachment belongs to, a 404 for the attachment if not even that.
+pub fn check_access(state: &Server, user: User, attachment: &Attachment, access: Access) -> Result::<(), ApiError> {
+    match state.get_note_as(user, &attachment.note_uuid, access) {
+        Ok(..) => Ok(()),
+        Err(ApiError::NoteNotFound) => Err(ApiError::NotFound),
+        Err(e) => Err(e)
+    }
+}
+
+#[get("/notes/{uuid}/attachments")]
+async fn list_attachments(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_note_as(user, &uuid, Access::Read)?;
+    let conn = state.pool.get()?;
+    let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM attachments WHERE note_uuid = ?1 ORDER BY created_at, file_name"))?;
+    let attachments = stmt.query_map(params![uuid.to_string()], from_row)?.collect::<Result::<Vec::<_>>>()?;
+    Ok(HttpResponse::Ok().json(attachments))
+}
+
+/// Takes a `multipart/form-data` body, every part with a file name is attached to the note.
+#[post("/notes/{uuid}/attachments")]
+async fn upload_attachments(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, req: HttpRequest, mut multipart: Multipart) -> ApiResult {
+    state.get_note_as(user, &uuid, Access::Write)?;
+    let mut storage_left = state.config.max_storage.saturating_sub(storage_used(&*state.pool.get()?)?);
+    let content_length = req.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if content_length.is_some_and(|content_length| content_length > storage_left) {
+        return Err(ApiError::QuotaExceeded(NO_ROOM_LEFT))
+    }
+
+    let mut attachments = Vec::new();
+    // the uploaded files, waiting to be linked once the attachments are inserted
+    let mut uploads = Vec::new();
+    // the files this upload stored first, the others belong to attachments made before
+    let mut created = Vec::new();
+    let stored = async {
+        while let Some(field) = multipart.next().await {
+            let mut field = field.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            let Some(file_name) = field.content_disposition().and_then(|cd| cd.get_filename()) else { continue };
+            let file_name = validate::strip_control_chars(file_name, false);
+            let content_type = content_type(&field, &file_name);
+            let (hash, size, tmp_path) = store_file(&mut field, state.config.max_attachment_size, storage_left).await?;
+            uploads.push((hash.clone(), tmp_path));
+            // only a guess, the quota is checked for real once the attachments are inserted
+            if !attachments.iter().any(|a: &Attachment| *a.hash == hash) && !file_path(&hash).exists() {
+                storage_left -= size
+            }
+            attachments.push(Attachment {
+                id: Uuid::new_v4(),
+                note_uuid: *uuid,
+                file_name,
+                content_type,
+                size,
+                hash: hash.into(),
+                created_at: unix_now()
+            });
+        }
+        if attachments.is_empty() { return Err(ApiError::BadRequest("no files in the request".to_owned())) }
+
+        let mut conn = state.pool.get()?;
+        // taking the write lock up front, so that uploads finishing at the same time can't both squeeze in
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        for a in attachments.iter() {
+            tx.execute(
+                &format!("INSERT INTO attachments ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"),
+                params![a.id.to_string(), a.note_uuid.to_string(), a.file_name, a.content_type, a.size, a.hash, a.created_at]
+            )?;
+        }
+        if storage_used(&tx)? > state.config.max_storage { return Err(ApiError::QuotaExceeded(NO_ROOM_LEFT)) }
+        // linking under the write lock, so that a file found already can't be removed before the attachments are in
+        for (hash, tmp_path) in uploads.iter() {
+            if link_file(tmp_path, hash).map_err(|e| ApiError::Internal(format!("could not store attachment: {e}")))? {
+                created.push(hash.clone())
+            }
+        }
+        tx.commit().map_err(ApiError::from)
+    }.await;
+
+    let tmp_paths = uploads.into_iter().map(|(_, tmp_path)| tmp_path).collect::<Vec::<_>>();
+    _ = blocking(move || Ok(tmp_paths.iter().for_each(|tmp_path| _ = fs::remove_file(tmp_path)))).await;
+    // don't leave the files of a failed upload behind
+    if let Err(e) = stored {
+        let conn = state.pool.get()?;
+        created.iter().try_for_each(|hash| remove_unused_file(&conn, hash))?;
+        return Err(e)
+    }
+    attachments.iter().for_each(|a| thumbnails::spawn_generate(a.hash.clone(), a.content_type.clone()));
+    Ok(HttpResponse::Created().json(attachments))
+}
+
+/// Serves the file itself, with support for range requests.
+#[get("/attachments/{id}")]
+async fn get_attachment_file(state: Data::<Server>, user: User, id: web::Path::<Uuid>, req: HttpRequest) -> ApiResult {
+    let attachment = get_attachment(&*state.pool.get()?, &id)?.ok_or(ApiError::NotFound)?;
+    check_access(&state, user, &attachment, Access::Read)?;
+
+    let file = NamedFile::open_async(file_path(&attachment.hash)).await.map_err(|e| {
+        ApiError::Internal(format!("could not open attachment file: {e}"))
+    })?;
+    let inline = INLINE_TYPES.contains(&&*attachment.content_type);
+    let mut resp = file
+        .set_content_type(attachment.content_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM))
+        .set_content_disposition(ContentDisposition {
+            disposition: if inline { DispositionType::Inline } else { DispositionType::Attachment },
+            parameters: vec![DispositionParam::Filename(attachment.file_name.into())]
+        })
+        .into_response(&req);
+    let headers = resp.headers_mut();
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+    Ok(resp)
+}
+
+#[delete("/attachments/{id}")]
+async fn delete_attachment(state: Data::<Server>, user: User, id: web::Path::<Uuid>) -> ApiResult {
+    let conn = state.pool.get()?;
+    let attachment = get_attachment(&conn, &id)?.ok_or(ApiError::NotFound)?;
+    check_access(&state, user, &attachment, Access::Write)?;
+    conn.execute("DELETE FROM attachments WHERE id = ?1", params![id.to_string()])?;
+    remove_unused_file(&conn, &attachment.hash)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_attachments)
+        .service(upload_attachments)
+        .service(get_attachment_file)
+        .service(delete_attachment);
+}
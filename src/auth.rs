@@ -0,0 +1,230 @@
+//! Devices have to be paired before they get at the notes. The QR code shown by the web UI on the machine running
+//! the server carries a one-time pairing token, which `POST /pair` trades, along with the name and password of a
+//! user, for a long-lived device token. The machine running the server only needs the name and password. Clients
+//! send the token as `Authorization: Bearer <token>`, browsers get it in a cookie too, since `EventSource` and
+//! `WebSocket` can't set headers.
+//!
+//! Only the SHA-256 of device tokens is stored, revoking a device (or logging out of it) removes it.
+
+use crate::*;
+
+use std::time::Instant;
+
+use actix_web::{
+    HttpRequest, HttpMessage, Error,
+    body::MessageBody,
+    middleware::Next,
+    dev::{ServiceRequest, ServiceResponse}
+};
+use sha2::{Digest, Sha256};
+
+/// How long the pairing token in a QR code can be redeemed for.
+pub const PAIRING_TOKEN_TTL: Duration = Duration::from_secs(5 * 60);
+pub const HOST_ONLY: &str = "only allowed from the machine running the server";
+const COOKIE_NAME: &str = "internotes_token";
+const COOKIE_MAX_AGE: u64 = 10 * 365 * 24 * 60 * 60;
+
+#[derive(Serialize)]
+struct Device {
+    id: Uuid,
+    name: Box::<str>,
+    created_at: UnixTimeStamp,
+    /// Whether it's the device the request came from.
+    current: bool
+}
+
+#[derive(Deserialize)]
+struct PairRequest {
+    /// Only left out on the machine running the server.
+    #[serde(default)]
+    token: Option::<Box::<str>>,
+    name: Box::<str>,
+    user: Box::<str>,
+    password: Box::<str>
+}
+
+/// The device a request was made from and the user logged in on it.
+#[derive(Clone, Copy)]
+pub struct Session {
+    pub device: Uuid,
+    pub user: User
+}
+
+pub struct Devices {
+    /// Sessions of the paired devices, by the hash of their token.
+    tokens: DashMap::<Box::<str>, Session>,
+    /// Pairing tokens handed out and not redeemed yet, along with when they expire.
+    pairing: Mutex::<Vec::<(Box::<str>, Instant)>>
+}
+
+#[inline]
+pub fn hash(token: &str) -> Box::<str> {
+    format!("{:x}", Sha256::digest(token.as_bytes())).into()
+}
+
+/// A long-lived secret, 244 random bits.
+#[inline]
+pub fn new_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+impl Devices {
+    pub fn load(conn: &Connection) -> Result::<Self> {
+        let tokens = conn.prepare("SELECT token_hash, id, user_id FROM devices")?.query_map([], |row| {
+            Ok((row.get::<_, Box::<str>>(0)?, Session {
+                device: Uuid::parse_str(&row.get::<_, String>(1)?).expect("invalid UUID"),
+                user: User(Uuid::parse_str(&row.get::<_, String>(2)?).expect("invalid UUID"))
+            }))
+        })?.collect::<Result::<DashMap::<_, _>>>()?;
+        Ok(Self { tokens, pairing: Mutex::default() })
+    }
+
+    /// Hands out a new pairing token, the ones handed out before stay valid until they expire.
+    pub fn pairing_token(&self) -> Box::<str> {
+        let token = Box::<str>::from(Uuid::new_v4().simple().to_string());
+        let now = Instant::now();
+        let mut pairing = self.pairing.lock().unwrap();
+        pairing.retain(|&(_, expires_at)| expires_at > now);
+        pairing.push((Box::clone(&token), now + PAIRING_TOKEN_TTL));
+        token
+    }
+
+    /// Whether `token` is still up for redeeming, and will be for `for_at_least`.
+    pub fn is_pending(&self, token: &str, for_at_least: Duration) -> bool {
+        let deadline = Instant::now() + for_at_least;
+        self.pairing.lock().unwrap().iter().any(|(pairing_token, expires_at)| **pairing_token == *token && *expires_at > deadline)
+    }
+
+    /// Uses up a pairing token, returns whether it was still valid.
+    fn redeem(&self, token: &str) -> bool {
+        let now = Instant::now();
+        let mut pairing = self.pairing.lock().unwrap();
+        pairing.retain(|&(_, expires_at)| expires_at > now);
+        let Some(i) = pairing.iter().position(|(pairing_token, _)| **pairing_token == *token) else { return false };
+        pairing.swap_remove(i);
+        true
+    }
+
+    /// Whether the device of `session` is still logged in, for connections that outlive the request they came in on.
+    #[inline]
+    pub fn is_active(&self, session: &Session) -> bool {
+        self.tokens.iter().any(|e| e.device == session.device)
+    }
+
+    #[inline]
+    fn session(&self, token: &str) -> Option::<Session> {
+        self.tokens.get(&hash(token)).map(|e| *e)
+    }
+}
+
+/// Whether the request comes from the machine running the server.
+#[inline]
+pub fn is_host(req: &HttpRequest) -> bool {
+    req.peer_addr().is_some_and(|peer| peer.ip().is_loopback() || peer.ip() == req.app_config().local_addr().ip())
+}
+
+/// The `Set-Cookie` value handing the device token to browsers, only ever sent back over HTTPS when served over it.
+fn cookie(req: &HttpRequest, token: &str, max_age: u64) -> String {
+    let secure = if req.app_config().secure() { "; Secure" } else { "" };
+    format!("{COOKIE_NAME}={token}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Strict{secure}")
+}
+
+/// The device token of the request, from the `Authorization` header or else the cookie.
+fn token(req: &HttpRequest) -> Option::<&str> {
+    if let Some(authorization) = req.headers().get(header::AUTHORIZATION) {
+        return authorization.to_str().ok()?.strip_prefix("Bearer ")
+    }
+    req.headers().get_all(header::COOKIE)
+        .filter_map(|cookie| cookie.to_str().ok())
+        .flat_map(|cookie| cookie.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))
+}
+
+/// The paired device the request was made from, if any.
+#[inline]
+pub fn device(state: &Server, req: &HttpRequest) -> Option::<Uuid> {
+    token(req).and_then(|token| state.devices.session(token)).map(|session| session.device)
+}
+
+/// Middleware turning away requests from devices that aren't paired, and telling the others apart, see [`User`].
+pub async fn authenticate(req: ServiceRequest, next: Next::<impl MessageBody>) -> Result::<ServiceResponse::<impl MessageBody>, Error> {
+    let state = req.app_data::<Data::<Server>>().expect("missing server state");
+    let Some(session) = token(req.request()).and_then(|token| state.devices.session(token)) else {
+        return Err(ApiError::Unauthorized("this device is not logged in").into())
+    };
+    req.extensions_mut().insert(session);
+    next.call(req).await
+}
+
+/// Logs a device in, one of the only endpoints open to devices that aren't.
+#[post("/pair")]
+pub async fn pair(state: Data::<Server>, req: HttpRequest, json: Json::<PairRequest>) -> ApiResult {
+    let PairRequest { token, mut name, user, password } = json.into_inner();
+    validate::device_name(&mut name, &state.config)?;
+    // checked first, so that a wrong password doesn't use up the pairing token
+    let user = User(users::verify(&state, &user, password).await?);
+    if !is_host(&req) && !token.is_some_and(|token| state.devices.redeem(&token)) {
+        return Err(ApiError::Unauthorized("invalid or expired pairing token"))
+    }
+
+    let device = Device { id: Uuid::new_v4(), name, created_at: unix_now(), current: true };
+    let token = new_token();
+    let token_hash = hash(&token);
+    state.pool.get()?.execute(
+        "INSERT INTO devices (id, user_id, name, token_hash, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![device.id.to_string(), user.0.to_string(), device.name, token_hash, device.created_at]
+    )?;
+    state.devices.tokens.insert(token_hash, Session { device: device.id, user });
+    Ok(HttpResponse::Created()
+        .insert_header((header::SET_COOKIE, cookie(&req, &token, COOKIE_MAX_AGE)))
+        .json(json!({
+            "device": device,
+            "token": token
+        })))
+}
+
+/// Devices the user is logged in on.
+#[get("/devices")]
+async fn list_devices(state: Data::<Server>, session: web::ReqData::<Session>) -> ApiResult {
+    let conn = state.pool.get()?;
+    let devices = conn.prepare("SELECT id, name, created_at FROM devices WHERE user_id = ?1 ORDER BY created_at")?
+        .query_map(params![session.user.0.to_string()], |row| {
+            let id = Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID");
+            Ok(Device { id, name: row.get(1)?, created_at: row.get(2)?, current: id == session.device })
+        })?
+        .collect::<Result::<Vec::<_>>>()?;
+    Ok(HttpResponse::Ok().json(devices))
+}
+
+fn remove_device(state: &Server, user: User, id: &Uuid) -> Result::<(), ApiError> {
+    let removed = state.pool.get()?.execute(
+        "DELETE FROM devices WHERE id = ?1 AND user_id = ?2",
+        params![id.to_string(), user.0.to_string()]
+    )?;
+    if removed == 0 { return Err(ApiError::NotFound) }
+    state.devices.tokens.retain(|_, session| session.device != *id);
+    Ok(())
+}
+
+/// Revokes the token of one of the user's devices, closing the streams and sockets it has open by their next event.
+#[delete("/devices/{id}")]
+async fn revoke_device(state: Data::<Server>, user: User, id: web::Path::<Uuid>) -> ApiResult {
+    remove_device(&state, user, &id)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Revokes the token of the device the request came from.
+#[post("/logout")]
+async fn logout(state: Data::<Server>, req: HttpRequest, session: web::ReqData::<Session>) -> ApiResult {
+    remove_device(&state, session.user, &session.device)?;
+    Ok(HttpResponse::NoContent()
+        .insert_header((header::SET_COOKIE, cookie(&req, "", 0)))
+        .finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_devices)
+        .service(revoke_device)
+        .service(logout);
+}
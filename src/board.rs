@@ -0,0 +1,113 @@
+//! Notes grouped into one column per status, ordered within a column by [`Note::column_position`],
+//! which is fractional the same way [`Note::position`] is, see [`ordering`].
+
+use crate::*;
+use crate::api::NotesQuery;
+
+#[derive(Serialize)]
+struct Column<'a> {
+    status: &'a Status,
+    color: &'a str,
+    done: bool,
+    /// Whether the status is defined in the workflow, notes may still be in one that got removed.
+    defined: bool,
+    notes: Vec::<Arc::<Note>>
+}
+
+#[derive(Deserialize)]
+struct Move {
+    status: Status,
+    /// Index within the target column to move the note to, the end of the column if left out.
+    index: Option::<usize>
+}
+
+const UNKNOWN_STATUS_COLOR: &str = "#6c757d";
+
+/// Live notes in `status` except `exclude`, in board order.
+fn column(state: &Server, status: &Status, exclude: Option::<&Uuid>) -> Vec::<Arc::<Note>> {
+    let mut notes = state.notes.iter()
+        .filter(|e| !e.is_trashed() && e.status == *status && Some(e.key()) != exclude)
+        .map(|e| Arc::clone(e.value()))
+        .collect::<Vec::<_>>();
+    sort_column(&mut notes);
+    notes
+}
+
+#[inline]
+fn sort_column(notes: &mut [Arc::<Note>]) {
+    notes.sort_by(|a, b| a.column_position.total_cmp(&b.column_position).then_with(|| a.uuid.cmp(&b.uuid)))
+}
+
+#[inline]
+pub fn next_column_position(state: &Server, status: &Status) -> f64 {
+    column(state, status, None).last().map_or(0.0, |note| note.column_position + 1.0)
+}
+
+#[inline]
+fn position_at(column: &[Arc::<Note>], index: usize) -> Option::<f64> {
+    ordering::position_at(column, index, |note| note.column_position)
+}
+
+#[get("/board")]
+async fn get_board(state: Data::<Server>, user: User, query: web::Query::<NotesQuery>) -> ApiResult {
+    let filter = query.filter(&state.workflow.read().unwrap())?;
+    let notes = state.live_notes(user).into_iter().filter(|note| filter(note)).collect::<Vec::<_>>();
+
+    let workflow = state.workflow.read().unwrap();
+    let mut columns = workflow.statuses.iter().map(|def| Column {
+        status: &def.name,
+        color: &def.color,
+        done: def.done,
+        defined: true,
+        notes: Vec::new()
+    }).collect::<Vec::<_>>();
+
+    for note in notes.iter() {
+        match columns.iter_mut().find(|column| *column.status == note.status) {
+            Some(column) => column.notes.push(Arc::clone(note)),
+            None => columns.push(Column {
+                status: &note.status,
+                color: UNKNOWN_STATUS_COLOR,
+                done: false,
+                defined: false,
+                notes: vec![Arc::clone(note)]
+            })
+        }
+    }
+
+    columns.iter_mut().for_each(|column| sort_column(&mut column.notes));
+    Ok(HttpResponse::Ok().json(json!({"columns": columns})))
+}
+
+/// Moves the note into another column and/or to another place within it in a single update.
+#[post("/notes/{uuid}/move")]
+async fn move_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, json: Json::<Move>) -> ApiResult {
+    let Move { status, index } = json.into_inner();
+    state.get_note_as(user, &uuid, Access::Write)?;
+    state.check_transition(&uuid, &status)?;
+
+    // the index is into the column as the user sees it
+    let mut column = column(&state, &status, Some(&uuid));
+    column.retain(|note| state.can_read(user, note));
+    let index = index.unwrap_or(column.len()).min(column.len());
+    let position = match position_at(&column, index) {
+        Some(position) => position,
+        None => ordering::renumber(
+            &state, user, &mut column, index,
+            |note| note.column_position,
+            |note, position| note.column_position = position
+        )
+    };
+
+    let note = state.modify_note(&uuid, |note| {
+        note.status = status;
+        note.column_position = position;
+    }).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(get_board)
+        .service(move_note);
+}
@@ -0,0 +1,142 @@
+//! Checklist items live on the cached [`Note`] in order, `checklist_items` is rewritten from them on every flush.
+
+use crate::*;
+use crate::validate::Validator;
+
+use serde::Serializer;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChecklistItem {
+    id: Uuid,
+    text: Box::<str>,
+    checked: bool
+}
+
+impl ChecklistItem {
+    /// A fresh unchecked copy, for the next occurrence of a recurring note.
+    #[inline]
+    pub fn unchecked(&self) -> Self {
+        Self { id: Uuid::new_v4(), text: self.text.clone(), checked: false }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewItem {
+    text: Box::<str>,
+    #[serde(default)]
+    checked: bool,
+    /// Where to insert the item, at the end if left out.
+    index: Option::<usize>
+}
+
+#[derive(Deserialize)]
+struct ItemPatch {
+    text: Option::<Box::<str>>,
+    checked: Option::<bool>,
+    index: Option::<usize>
+}
+
+pub fn serialize_progress<S: Serializer>(items: &[ChecklistItem], ser: S) -> Result::<S::Ok, S::Error> {
+    let checked = items.iter().filter(|item| item.checked).count();
+    json!({"checked": checked, "total": items.len()}).serialize(ser)
+}
+
+pub fn load_note_items(conn: &Connection, notes: &Notes) -> Result::<()> {
+    let mut stmt = conn.prepare("SELECT note_uuid, id, text, checked FROM checklist_items ORDER BY note_uuid, position")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let uuid = Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID");
+        if let Some(mut note) = notes.get_mut(&uuid) {
+            Arc::make_mut(&mut note).items.push(ChecklistItem {
+                id: Uuid::parse_str(&row.get::<_, String>(1)?).expect("invalid UUID"),
+                text: row.get(2)?,
+                checked: row.get(3)?
+            })
+        }
+    }
+    Ok(())
+}
+
+pub fn save_note_items(conn: &Connection, note: &Note) -> Result::<()> {
+    let uuid = note.uuid.to_string();
+    conn.execute("DELETE FROM checklist_items WHERE note_uuid = ?1", params![uuid])?;
+    for (position, item) in note.items.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO checklist_items (id, note_uuid, text, checked, position) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![item.id.to_string(), uuid, item.text, item.checked, position]
+        )?;
+    }
+    Ok(())
+}
+
+fn validate_text(text: &mut Box::<str>, config: &Config) -> Result::<(), ApiError> {
+    let mut v = Validator::default();
+    v.text("text", text, config.max_title_len, false);
+    if text.trim().is_empty() { v.error("text", "must not be empty") }
+    v.finish().map_err(ApiError::Validation)
+}
+
+#[inline(always)]
+fn item_location(uuid: &Uuid, id: &Uuid) -> String {
+    format!("/api/v1/notes/{uuid}/items/{id}")
+}
+
+#[get("/notes/{uuid}/items")]
+async fn list_items(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    let note = state.get_note_as(user, &uuid, Access::Read)?;
+    Ok(HttpResponse::Ok().json(&note.items))
+}
+
+#[post("/notes/{uuid}/items")]
+async fn create_item(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, json: Json::<NewItem>) -> ApiResult {
+    let NewItem { mut text, checked, index } = json.into_inner();
+    validate_text(&mut text, &state.config)?;
+    state.get_note_as(user, &uuid, Access::Write)?;
+    let item = ChecklistItem { id: Uuid::new_v4(), text, checked };
+    state.modify_note(&uuid, |note| {
+        let index = index.unwrap_or(note.items.len()).min(note.items.len());
+        note.items.insert(index, item.clone())
+    }).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, item_location(&uuid, &item.id)))
+        .json(item))
+}
+
+#[patch("/notes/{uuid}/items/{id}")]
+async fn update_item(state: Data::<Server>, user: User, path: web::Path::<(Uuid, Uuid)>, json: Json::<ItemPatch>) -> ApiResult {
+    let (uuid, id) = path.into_inner();
+    let ItemPatch { mut text, checked, index } = json.into_inner();
+    if let Some(ref mut text) = text { validate_text(text, &state.config)? }
+
+    let note = state.get_note_as(user, &uuid, Access::Write)?;
+    if !note.items.iter().any(|item| item.id == id) { return Err(ApiError::NotFound) }
+
+    let mut updated = None;
+    state.modify_note(&uuid, |note| {
+        let Some(i) = note.items.iter().position(|item| item.id == id) else { return };
+        let mut item = note.items.remove(i);
+        if let Some(text) = text { item.text = text }
+        if let Some(checked) = checked { item.checked = checked }
+        let index = index.unwrap_or(i).min(note.items.len());
+        note.items.insert(index, item.clone());
+        updated = Some(item)
+    }).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(updated.ok_or(ApiError::NotFound)?))
+}
+
+#[delete("/notes/{uuid}/items/{id}")]
+async fn delete_item(state: Data::<Server>, user: User, path: web::Path::<(Uuid, Uuid)>) -> ApiResult {
+    let (uuid, id) = path.into_inner();
+    let note = state.get_note_as(user, &uuid, Access::Write)?;
+    if !note.items.iter().any(|item| item.id == id) { return Err(ApiError::NotFound) }
+    state.modify_note(&uuid, |note| note.items.retain(|item| item.id != id)).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_items)
+        .service(create_item)
+        .service(update_item)
+        .service(delete_item);
+}
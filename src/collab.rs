@@ -0,0 +1,457 @@
+//! Collaborative editing of a note's description over a WebSocket at `/notes/{uuid}/ws`, using operational
+//! transformation. Operations are lists of components walking the text in Unicode scalar values (not UTF-16 units):
+//! a positive number retains that many characters, a negative one deletes that many, a string inserts itself.
+//!
+//! Every note being edited has a session with a revision counter, starting at 0 for its first participant.
+//! The server sends `{"type": "init", "rev", "text"}` on connecting, after which clients send `{"rev", "ops"}`
+//! with the revision their operation is based on. The server transforms it against everything that happened
+//! since, applies it to the note and answers with `{"type": "ack", "rev"}`, while the other participants get
+//! `{"type": "op", "rev", "ops"}`. Changes made through the REST API show up as operations too.
+//! A rejected operation gets `{"type": "error", "message"}`, after which the client has to reconnect.
+//! Operations are rate limited along with the requests of the device, see [`rate_limit`].
+//!
+//! Whether the client still gets to edit the note is checked again for every operation and every keep-alive, so
+//! that unsharing the note or logging the device out closes the socket.
+
+use crate::*;
+
+use actix_web::HttpRequest;
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const CHANNEL_CAPACITY: usize = 64;
+/// The origin of operations that come from edits made outside of the session.
+const SERVER: u64 = 0;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Component {
+    Retain(usize),
+    Insert(Box::<str>),
+    Delete(usize)
+}
+
+use Component::*;
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawComponent {
+    Count(i64),
+    Text(Box::<str>)
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(try_from = "Vec::<RawComponent>", into = "Vec::<RawComponent>")]
+pub struct Operation {
+    components: Vec::<Component>,
+    /// Length of the text the operation applies to.
+    base_len: usize,
+    /// Length of the text it results in.
+    target_len: usize
+}
+
+impl TryFrom::<Vec::<RawComponent>> for Operation {
+    type Error = String;
+
+    fn try_from(raw: Vec::<RawComponent>) -> Result::<Self, Self::Error> {
+        let mut op = Self::default();
+        // lengths that don't fit don't match any text either, and would wrap around
+        let too_long = || "operation is too long".to_owned();
+        for component in raw {
+            match component {
+                RawComponent::Count(0) => return Err("components must not be 0".to_owned()),
+                RawComponent::Count(n) if n > 0 => {
+                    let n = usize::try_from(n).map_err(|_| too_long())?;
+                    op.base_len.checked_add(n).zip(op.target_len.checked_add(n)).ok_or_else(too_long)?;
+                    op.retain(n)
+                }
+                RawComponent::Count(n) => {
+                    let n = usize::try_from(n.unsigned_abs()).map_err(|_| too_long())?;
+                    op.base_len.checked_add(n).ok_or_else(too_long)?;
+                    op.delete(n)
+                }
+                RawComponent::Text(text) if text.is_empty() => return Err("inserted text must not be empty".to_owned()),
+                RawComponent::Text(text) if text.chars().any(|c| c.is_control() && !matches!(c, '\n' | '\t')) => {
+                    return Err("inserted text must not contain control characters".to_owned())
+                }
+                RawComponent::Text(text) => {
+                    op.target_len.checked_add(text.chars().count()).ok_or_else(too_long)?;
+                    op.insert(text)
+                }
+            }
+        }
+        Ok(op)
+    }
+}
+
+impl From::<Operation> for Vec::<RawComponent> {
+    fn from(op: Operation) -> Self {
+        op.components.into_iter().map(|component| match component {
+            Retain(n) => RawComponent::Count(n as _),
+            Insert(text) => RawComponent::Text(text),
+            Delete(n) => RawComponent::Count(-(n as i64))
+        }).collect()
+    }
+}
+
+impl Operation {
+    fn retain(&mut self, n: usize) {
+        if n == 0 { return }
+        self.base_len += n;
+        self.target_len += n;
+        match self.components.last_mut() {
+            Some(Retain(last)) => *last += n,
+            _ => self.components.push(Retain(n))
+        }
+    }
+
+    /// Inserts always go before deletes at the same position, so that equal operations look the same.
+    fn insert(&mut self, text: Box::<str>) {
+        if text.is_empty() { return }
+        self.target_len += text.chars().count();
+        let len = self.components.len();
+        match self.components.as_mut_slice() {
+            [.., Insert(last)] | [.., Insert(last), Delete(..)] => *last = format!("{last}{text}").into(),
+            [.., Delete(..)] => self.components.insert(len - 1, Insert(text)),
+            _ => self.components.push(Insert(text))
+        }
+    }
+
+    fn delete(&mut self, n: usize) {
+        if n == 0 { return }
+        self.base_len += n;
+        match self.components.last_mut() {
+            Some(Delete(last)) => *last += n,
+            _ => self.components.push(Delete(n))
+        }
+    }
+
+    /// The operation turning `old` into `new`, replacing everything between their common prefix and suffix.
+    fn diff(old: &str, new: &str) -> Self {
+        let (old, new) = (old.chars().collect::<Vec::<_>>(), new.chars().collect::<Vec::<_>>());
+        let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+        let mut op = Self::default();
+        op.retain(prefix);
+        op.insert(new[prefix..new.len() - suffix].iter().collect::<String>().into());
+        op.delete(old.len() - prefix - suffix);
+        op.retain(suffix);
+        op
+    }
+
+    fn apply(&self, text: &str) -> Result::<String, String> {
+        let len = text.chars().count();
+        if len != self.base_len {
+            return Err(format!("operation applies to {base_len} characters, the text has {len}", base_len = self.base_len))
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        for component in self.components.iter() {
+            match component {
+                Retain(n) => out.extend(chars.by_ref().take(*n)),
+                Insert(text) => out.push_str(text),
+                Delete(n) => chars.by_ref().take(*n).for_each(drop)
+            }
+        }
+        Ok(out)
+    }
+
+    /// Transforms two operations made on the same text into `(a', b')`, so that applying `b'` after `a`
+    /// gives the same text as applying `a'` after `b`. When both insert at the same position, `a` goes first.
+    fn transform(a: &Self, b: &Self) -> Result::<(Self, Self), String> {
+        if a.base_len != b.base_len {
+            return Err(format!("operation applies to {a} characters, the text has {b}", a = a.base_len, b = b.base_len))
+        }
+        let (mut a_prime, mut b_prime) = (Self::default(), Self::default());
+        let (mut a_components, mut b_components) = (a.components.iter().cloned(), b.components.iter().cloned());
+        let (mut a_next, mut b_next) = (a_components.next(), b_components.next());
+        loop {
+            match (a_next.take(), b_next.take()) {
+                (None, None) => break,
+                (Some(Insert(text)), b) => {
+                    b_prime.retain(text.chars().count());
+                    a_prime.insert(text);
+                    (a_next, b_next) = (a_components.next(), b)
+                }
+                (a, Some(Insert(text))) => {
+                    a_prime.retain(text.chars().count());
+                    b_prime.insert(text);
+                    (a_next, b_next) = (a, b_components.next())
+                }
+                (None, _) | (_, None) => return Err("operations have different lengths".to_owned()),
+                (Some(a), Some(b)) => {
+                    let (a_len, b_len) = match (&a, &b) {
+                        (Retain(a) | Delete(a), Retain(b) | Delete(b)) => (*a, *b),
+                        _ => unreachable!()
+                    };
+                    let n = a_len.min(b_len);
+                    match (&a, &b) {
+                        (Retain(..), Retain(..)) => { a_prime.retain(n); b_prime.retain(n) }
+                        (Delete(..), Retain(..)) => a_prime.delete(n),
+                        (Retain(..), Delete(..)) => b_prime.delete(n),
+                        // both deleted the same characters
+                        _ => {}
+                    }
+                    let rest = |component: &Component, len: usize| match component {
+                        Retain(..) => Retain(len - n),
+                        _ => Delete(len - n)
+                    };
+                    a_next = if a_len > n { Some(rest(&a, a_len)) } else { a_components.next() };
+                    b_next = if b_len > n { Some(rest(&b, b_len)) } else { b_components.next() };
+                }
+            }
+        }
+        Ok((a_prime, b_prime))
+    }
+}
+
+struct Session {
+    rev: u64,
+    text: String,
+    /// `history[i]` turns revision `i` into `i + 1`.
+    history: Vec::<Operation>,
+    participants: usize,
+    next_client: u64,
+    /// Committed operations as `(origin, rev, message)`.
+    sender: broadcast::Sender::<(u64, u64, Arc::<str>)>
+}
+
+impl Session {
+    fn new(text: &str) -> Self {
+        Self {
+            rev: 0,
+            text: text.to_owned(),
+            history: Vec::new(),
+            participants: 0,
+            next_client: SERVER,
+            sender: broadcast::channel(CHANNEL_CAPACITY).0
+        }
+    }
+
+    fn commit(&mut self, origin: u64, op: Operation) {
+        self.rev += 1;
+        let message = json!({"type": "op", "rev": self.rev, "ops": op}).to_string();
+        self.history.push(op);
+        // nobody listening is fine
+        _ = self.sender.send((origin, self.rev, message.into()))
+    }
+
+    /// Catches up with changes made to the note outside of the session, returns `false` if it's gone.
+    fn sync(&mut self, state: &Server, uuid: &Uuid) -> bool {
+        let Some(note) = state.get_note(uuid) else { return false };
+        if *note.description != *self.text {
+            let op = Operation::diff(&self.text, &note.description);
+            self.text = note.description.to_string();
+            self.commit(SERVER, op)
+        }
+        true
+    }
+
+    fn receive(&mut self, state: &Server, uuid: &Uuid, client: u64, rev: u64, mut op: Operation) -> Result::<(), String> {
+        if !self.sync(state, uuid) { return Err("note not found".to_owned()) }
+        if rev > self.rev {
+            return Err(format!("revision {rev} is ahead of the session, which is at {rev_now}", rev_now = self.rev))
+        }
+        for concurrent in self.history[rev as usize..].iter() {
+            op = Operation::transform(&op, concurrent)?.0
+        }
+        let text = op.apply(&self.text)?;
+        let len = text.chars().count();
+        if len > state.config.max_description_len {
+            return Err(format!("description must be at most {max} characters long, got {len}", max = state.config.max_description_len))
+        }
+        state.modify_note(uuid, |note| {
+            note.description = text.as_str().into();
+            note.mod_time = unix_now();
+        }).ok_or("note not found")?;
+        self.text = text;
+        self.commit(client, op);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Sessions(DashMap::<Uuid, Arc::<Mutex::<Session>>>);
+
+type Joined = (Arc::<Mutex::<Session>>, u64, broadcast::Receiver::<(u64, u64, Arc::<str>)>, String);
+
+impl Sessions {
+    /// Returns the session, the ID of the client within it, its operations, and the `init` message.
+    fn join(&self, state: &Server, uuid: Uuid) -> Option::<Joined> {
+        let note = state.get_note(&uuid)?;
+        // the entry stays locked until the participant is counted, so that `leave` can't drop the session meanwhile
+        let entry = self.0.entry(uuid).or_insert_with(|| Arc::new(Mutex::new(Session::new(&note.description))));
+        let mut session = entry.lock().unwrap();
+        if !session.sync(state, &uuid) { return None }
+        session.participants += 1;
+        session.next_client += 1;
+        let init = json!({"type": "init", "rev": session.rev, "text": session.text}).to_string();
+        let joined = (Arc::clone(&entry), session.next_client, session.sender.subscribe(), init);
+        drop(session);
+        Some(joined)
+    }
+
+    fn leave(&self, uuid: &Uuid, session: &Mutex::<Session>) {
+        session.lock().unwrap().participants -= 1;
+        self.0.remove_if(uuid, |_, session| session.lock().unwrap().participants == 0);
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientOp {
+    rev: u64,
+    ops: Operation
+}
+
+/// Whether the client can still edit the note, `None` if so, or else why it can't.
+fn revoked(state: &Server, session: &auth::Session, uuid: &Uuid) -> Option::<&'static str> {
+    if !state.devices.is_active(session) { return Some("this device is not logged in") }
+    match state.get_note_as(session.user, uuid, Access::Write) {
+        Ok(..) => None,
+        Err(ApiError::Forbidden(message)) => Some(message),
+        Err(..) => Some("note not found")
+    }
+}
+
+async fn serve(state: Data::<Server>, auth: auth::Session, uuid: Uuid, mut ws: actix_ws::Session, mut messages: AggregatedMessageStream) {
+    let Some((session, client, mut ops, init)) = state.collab.join(&state, uuid) else {
+        _ = ws.close(Some((CloseCode::Normal, "note not found").into())).await;
+        return
+    };
+    let mut shutdown = state.shutdown.clone();
+    let mut keep_alive = actix_rt::time::interval(reminders::KEEP_ALIVE_INTERVAL);
+    keep_alive.reset();
+
+    let reason = if ws.text(init).await.is_err() { None } else { loop {
+        tokio::select! {
+            _ = shutdown.changed() => break Some(CloseCode::Away.into()),
+            _ = keep_alive.tick() => {
+                // picks up edits made through the REST API while nobody is typing
+                let alive = session.lock().unwrap().sync(&state, &uuid);
+                if !alive { break Some((CloseCode::Normal, "note not found").into()) }
+                if let Some(reason) = revoked(&state, &auth, &uuid) { break Some((CloseCode::Policy, reason).into()) }
+                if ws.ping(b"").await.is_err() { break None }
+            }
+            op = ops.recv() => {
+                // own operations are acknowledged in the same order everyone else gets them
+                let sent = match op {
+                    Ok((origin, rev, ..)) if origin == client => ws.text(json!({"type": "ack", "rev": rev}).to_string()).await,
+                    Ok((.., message)) => ws.text(&*message).await,
+                    Err(RecvError::Lagged(..)) => break Some((CloseCode::Again, "fell behind").into()),
+                    Err(RecvError::Closed) => break None
+                };
+                if sent.is_err() { break None }
+            }
+            message = messages.recv() => match message {
+                Some(Ok(AggregatedMessage::Text(text))) => {
+                    if let Some(reason) = revoked(&state, &auth, &uuid) { break Some((CloseCode::Policy, reason).into()) }
+                    let received = state.rate_limits.take_op(&auth, &state.config)
+                        .map_err(|retry_after| format!("too many changes, try again in {retry_after} seconds"))
+                        .and_then(|_| serde_json::from_str::<ClientOp>(&text).map_err(|e| e.to_string()))
+                        .and_then(|ClientOp { rev, ops }| session.lock().unwrap().receive(&state, &uuid, client, rev, ops));
+                    if let Err(e) = received {
+                        if ws.text(json!({"type": "error", "message": e}).to_string()).await.is_err() { break None }
+                    }
+                }
+                Some(Ok(AggregatedMessage::Ping(bytes))) => if ws.pong(&bytes).await.is_err() { break None },
+                Some(Ok(AggregatedMessage::Binary(..) | AggregatedMessage::Pong(..))) => {}
+                Some(Ok(AggregatedMessage::Close(..)) | Err(..)) | None => break None
+            }
+        }
+    }};
+
+    state.collab.leave(&uuid, &session);
+    _ = ws.close(reason).await;
+}
+
+#[get("/notes/{uuid}/ws")]
+async fn note_socket(state: Data::<Server>, session: web::ReqData::<auth::Session>, uuid: web::Path::<Uuid>, req: HttpRequest, body: web::Payload) -> ApiResult {
+    state.get_note_as(session.user, &uuid, Access::Write)?;
+    let (resp, ws, messages) = actix_ws::handle(&req, body).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let max_size = state.config.max_payload_size;
+    let messages = messages.max_frame_size(max_size).aggregate_continuations().max_continuation_size(max_size);
+    actix_rt::spawn(serve(state, session.into_inner(), uuid.into_inner(), ws, messages));
+    Ok(resp)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(note_socket);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(raw: serde_json::Value) -> Operation {
+        serde_json::from_value(raw).unwrap()
+    }
+
+    /// Transforms `a` and `b` made on `text` both ways and checks they end up with the same text.
+    fn converge(text: &str, a: &Operation, b: &Operation) -> String {
+        let (a_prime, b_prime) = Operation::transform(a, b).unwrap();
+        let ab = b_prime.apply(&a.apply(text).unwrap()).unwrap();
+        let ba = a_prime.apply(&b.apply(text).unwrap()).unwrap();
+        assert_eq!(ab, ba);
+        ab
+    }
+
+    #[test]
+    fn parses_and_normalizes_components() {
+        let parsed = op(json!([2, 1, -1, "ab", -2, "c", 3]));
+        assert_eq!(parsed.components, [Retain(3), Insert("abc".into()), Delete(3), Retain(3)]);
+        assert_eq!((parsed.base_len, parsed.target_len), (9, 9));
+        assert_eq!(serde_json::to_value(parsed).unwrap(), json!([3, "abc", -3, 3]));
+    }
+
+    #[test]
+    fn rejects_invalid_components() {
+        for raw in [
+            "[0]", r#"[""]"#, r#"["\u0007"]"#, "[1.5]", "[null]",
+            "[9223372036854775807, 9223372036854775807, 9223372036854775807]",
+            "[-9223372036854775808, -9223372036854775808, -9223372036854775808]"
+        ] {
+            assert!(serde_json::from_str::<Operation>(raw).is_err(), "{raw}")
+        }
+        assert!(serde_json::from_str::<Operation>(r#"["a\nb\tc"]"#).is_ok());
+    }
+
+    #[test]
+    fn applies_in_characters() {
+        assert_eq!(op(json!([1, -1, "ö", 2])).apply("héé!").unwrap(), "höé!");
+        assert!(op(json!([3])).apply("héé!").is_err());
+        assert!(op(json!([5])).apply("héé!").is_err());
+    }
+
+    #[test]
+    fn inserts_at_the_same_position_put_the_first_one_first() {
+        let (a, b) = (op(json!([2, "A", 3])), op(json!([2, "B", 3])));
+        assert_eq!(converge("hello", &a, &b), "heABllo");
+        assert_eq!(converge("hello", &b, &a), "heBAllo");
+    }
+
+    #[test]
+    fn delete_and_insert_converge() {
+        // the insert lands right where the deleted text was
+        assert_eq!(converge("hello world", &op(json!([2, -7, 2])), &op(json!([5, "!", 6]))), "he!ld");
+        // deleted next to each other, and overlapping
+        assert_eq!(converge("hello world", &op(json!([-5, 6])), &op(json!([5, -6]))), "");
+        assert_eq!(converge("hello world", &op(json!([2, -5, 4])), &op(json!([4, -5, 2]))), "held");
+        assert_eq!(converge("hello world", &op(json!([-6, "bye ", 5])), &op(json!([6, -5, "there"]))), "bye there");
+        assert_eq!(converge("ünï", &op(json!([1, -1, 1])), &op(json!([3, "ç"]))), "üïç");
+    }
+
+    #[test]
+    fn transform_rejects_operations_on_different_texts() {
+        assert!(Operation::transform(&op(json!([3])), &op(json!([4]))).is_err());
+    }
+
+    #[test]
+    fn diff_turns_old_into_new() {
+        for (old, new) in [("", ""), ("", "abc"), ("abc", ""), ("hello world", "hello there world"), ("aaa", "aa"), ("héllo", "hällo"), ("abc", "xyz")] {
+            let diff = Operation::diff(old, new);
+            assert_eq!(diff.apply(old).unwrap(), new, "{old:?} -> {new:?}");
+            assert_eq!((diff.base_len, diff.target_len), (old.chars().count(), new.chars().count()))
+        }
+        assert_eq!(Operation::diff("hello world", "hello there world").components, [Retain(6), Insert("there ".into()), Retain(5)]);
+        assert_eq!(Operation::diff("abc", "axc").components, [Retain(1), Insert("x".into()), Delete(1), Retain(1)]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+use std::fmt::Display;
+use std::time::Duration;
+use std::path::PathBuf;
+
+pub const ENV_PREFIX: &str = "INTERNOTES_";
+
+pub const DEFAULT_MAX_TITLE_LEN: usize = 256;
+pub const DEFAULT_MAX_DESCRIPTION_LEN: usize = 64 * 1024;
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 256 * 1024;
+pub const DEFAULT_MAX_TAG_LEN: usize = 64;
+pub const DEFAULT_MAX_TAGS: usize = 32;
+pub const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: usize = 25 * 1024 * 1024;
+pub const DEFAULT_MAX_NOTES: usize = 100_000;
+pub const DEFAULT_MAX_STORAGE: u64 = 10 * 1024 * 1024 * 1024;
+pub const DEFAULT_RATE_LIMIT_BURST: u32 = 60;
+pub const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 120;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Maximum length of a note title, in characters.
+    pub max_title_len: usize,
+    /// Maximum length of a note description, in characters.
+    pub max_description_len: usize,
+    /// Maximum length of a tag or notebook name, in characters.
+    pub max_tag_len: usize,
+    /// Maximum number of tags on a single note.
+    pub max_tags: usize,
+    /// Maximum size of a JSON request body, in bytes.
+    pub max_payload_size: usize,
+    /// How long a note stays in the trash before it's removed for good.
+    pub trash_retention: Duration,
+    /// Maximum size of a single attached file, in bytes.
+    pub max_attachment_size: usize,
+    /// Maximum number of notes on the server, of every user and in the trash too.
+    pub max_notes: usize,
+    /// Maximum size of every attached file together, in bytes.
+    pub max_storage: u64,
+    /// How many changes a client gets to make in a row, see [`crate::rate_limit`].
+    pub rate_limit_burst: u32,
+    /// How many changes a client gets to make per minute after that, 0 for no limit.
+    pub rate_limit_per_minute: u32,
+    /// Whether to serve HTTPS (and HTTP/2) rather than plain HTTP.
+    pub tls: bool,
+    /// PEM certificate chain to serve HTTPS with, a self-signed one is made and kept if left unset.
+    pub tls_cert: Option::<PathBuf>,
+    /// PEM private key of `tls_cert`.
+    pub tls_key: Option::<PathBuf>,
+}
+
+/// Reads `INTERNOTES_<name>` from the environment, falling back to `default` if it's unset.
+fn env_or<T>(name: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Display
+{
+    let var = format!("{ENV_PREFIX}{name}");
+    match std::env::var(&var) {
+        Ok(val) => val.trim().parse().unwrap_or_else(|e| panic!("invalid value of {var}: {val:?}: {e}")),
+        Err(..) => default
+    }
+}
+
+/// Reads `INTERNOTES_<name>` from the environment as a path, if it's set.
+fn env_path(name: &str) -> Option::<PathBuf> {
+    std::env::var_os(format!("{ENV_PREFIX}{name}")).filter(|val| !val.is_empty()).map(PathBuf::from)
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            max_title_len: env_or("MAX_TITLE_LEN", DEFAULT_MAX_TITLE_LEN),
+            max_description_len: env_or("MAX_DESCRIPTION_LEN", DEFAULT_MAX_DESCRIPTION_LEN),
+            max_tag_len: env_or("MAX_TAG_LEN", DEFAULT_MAX_TAG_LEN),
+            max_tags: env_or("MAX_TAGS", DEFAULT_MAX_TAGS),
+            max_payload_size: env_or("MAX_PAYLOAD_SIZE", DEFAULT_MAX_PAYLOAD_SIZE),
+            trash_retention: Duration::from_secs(env_or("TRASH_RETENTION_DAYS", DEFAULT_TRASH_RETENTION_DAYS) * SECS_PER_DAY),
+            max_attachment_size: env_or("MAX_ATTACHMENT_SIZE", DEFAULT_MAX_ATTACHMENT_SIZE),
+            max_notes: env_or("MAX_NOTES", DEFAULT_MAX_NOTES),
+            max_storage: env_or("MAX_STORAGE", DEFAULT_MAX_STORAGE),
+            rate_limit_burst: match env_or("RATE_LIMIT_BURST", DEFAULT_RATE_LIMIT_BURST) {
+                0 => panic!("invalid value of {ENV_PREFIX}RATE_LIMIT_BURST: 0: no change would ever get through"),
+                burst => burst
+            },
+            rate_limit_per_minute: env_or("RATE_LIMIT_PER_MINUTE", DEFAULT_RATE_LIMIT_PER_MINUTE),
+            tls: env_or("TLS", false),
+            tls_cert: env_path("TLS_CERT"),
+            tls_key: env_path("TLS_KEY"),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! The live change feed, a `text/event-stream` of every note created, updated or deleted (moved to the trash).
+//! `created` and `updated` events carry the note, `deleted` ones just its UUID.
+//!
+//! Event IDs are `<epoch>-<seq>`, where the epoch is when the server started. A client reconnecting with a
+//! `Last-Event-ID` gets the events it missed replayed, or a `reset` event telling it to fetch everything again
+//! when they are no longer around (the server restarted, or too much happened in the meantime).
+//!
+//! Every user only hears about the notes they can see, and gets a `deleted` event for a note that stops being
+//! shared with them. The stream ends once the device it was opened from is logged out.
+
+use crate::*;
+
+use std::convert::Infallible;
+use std::collections::VecDeque;
+
+use actix_web::HttpRequest;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const CHANNEL_CAPACITY: usize = 256;
+/// How many of the latest changes are kept around for clients resuming with `Last-Event-ID`.
+const HISTORY_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Display)]
+pub enum ChangeKind {
+    #[display("created")]
+    Created,
+    #[display("updated")]
+    Updated,
+    #[display("deleted")]
+    Deleted
+}
+
+#[derive(Clone)]
+struct Change {
+    id: u64,
+    note: Arc::<Note>,
+    /// The one user the event is meant for, instead of everyone who can see the note.
+    audience: Option::<User>,
+    /// The event as sent over the wire.
+    message: web::Bytes
+}
+
+#[derive(Default)]
+struct History {
+    next_id: u64,
+    changes: VecDeque::<Change>
+}
+
+pub struct Feed {
+    epoch: UnixTimeStamp,
+    history: Mutex::<History>,
+    sender: broadcast::Sender::<Change>
+}
+
+impl Feed {
+    pub fn new() -> Self {
+        Self {
+            epoch: unix_now(),
+            history: Mutex::new(History { next_id: 1, ..History::default() }),
+            sender: broadcast::channel(CHANNEL_CAPACITY).0
+        }
+    }
+
+    /// Called by [`Server`] on every change of a note outside of the trash.
+    #[inline]
+    pub fn publish(&self, kind: ChangeKind, note: &Arc::<Note>) {
+        self.push(kind, note, None)
+    }
+
+    /// Tells `user` the note is gone for them, after it stopped being shared with them.
+    #[inline]
+    pub fn revoke(&self, user: User, note: &Arc::<Note>) {
+        self.push(ChangeKind::Deleted, note, Some(user))
+    }
+
+    fn push(&self, kind: ChangeKind, note: &Arc::<Note>, audience: Option::<User>) {
+        let data = match kind {
+            ChangeKind::Deleted => json!({"uuid": note.uuid}),
+            ChangeKind::Created | ChangeKind::Updated => json!(note)
+        };
+        let mut history = self.history.lock().unwrap();
+        let id = history.next_id;
+        history.next_id += 1;
+        let change = Change {
+            id,
+            note: Arc::clone(note),
+            audience,
+            message: format!("id: {epoch}-{id}\nevent: {kind}\ndata: {data}\n\n", epoch = self.epoch).into()
+        };
+        if history.changes.len() == HISTORY_LEN { history.changes.pop_front(); }
+        history.changes.push_back(change.clone());
+        // nobody listening is fine
+        _ = self.sender.send(change)
+    }
+
+    /// Subscribes to the changes after `last_event_id`, returns what has to be sent before them.
+    fn subscribe(&self, state: &Server, user: User, last_event_id: Option::<&str>) -> (Vec::<web::Bytes>, broadcast::Receiver::<Change>) {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let latest = format!("{epoch}-{id}", epoch = self.epoch, id = history.next_id - 1);
+
+        // without a `Last-Event-ID` only the ID to resume from is sent
+        let Some(last_event_id) = last_event_id else { return (vec![format!("id: {latest}\n\n").into()], receiver) };
+        let oldest = history.changes.front().map_or(history.next_id, |change| change.id);
+        let resumable = last_event_id.split_once('-')
+            .and_then(|(epoch, id)| Some((epoch.parse::<UnixTimeStamp>().ok()?, id.parse::<u64>().ok()?)))
+            .filter(|&(epoch, id)| epoch == self.epoch && id + 1 >= oldest && id < history.next_id)
+            .map(|(.., id)| id);
+        let replay = match resumable {
+            Some(id) => history.changes.iter()
+                .filter(|change| change.id > id && change.is_for(state, user))
+                .map(|change| change.message.clone())
+                .collect(),
+            None => vec![format!("id: {latest}\nevent: reset\ndata: {{}}\n\n").into()]
+        };
+        (replay, receiver)
+    }
+}
+
+impl Change {
+    #[inline]
+    fn is_for(&self, state: &Server, user: User) -> bool {
+        match self.audience {
+            Some(audience) => audience == user,
+            None => state.can_read(user, &self.note)
+        }
+    }
+}
+
+#[get("/events")]
+async fn change_events(state: Data::<Server>, session: web::ReqData::<auth::Session>, req: HttpRequest) -> impl Responder {
+    let session = session.into_inner();
+    let user = session.user;
+    let last_event_id = req.headers().get("last-event-id").and_then(|id| id.to_str().ok());
+    let (replay, changes) = state.events.subscribe(&state, user, last_event_id);
+    let replay = futures_util::stream::iter(replay.into_iter().map(Ok::<_, Infallible>));
+    let shutdown = state.shutdown.clone();
+    let live = futures_util::stream::unfold((changes, shutdown, state), move |(mut changes, mut shutdown, state)| async move {
+        if *shutdown.borrow() { return None }
+        let message = loop {
+            tokio::select! {
+                _ = shutdown.changed() => return None,
+                change = actix_rt::time::timeout(reminders::KEEP_ALIVE_INTERVAL, changes.recv()) => {
+                    if !state.devices.is_active(&session) { return None }
+                    match change {
+                        Ok(Ok(change)) if change.is_for(&state, user) => break change.message,
+                        // someone else's note
+                        Ok(Ok(..)) => continue,
+                        // missed some changes, the client has to catch up by fetching everything
+                        Ok(Err(RecvError::Lagged(..))) => break web::Bytes::from_static(b"event: reset\ndata: {}\n\n"),
+                        Ok(Err(RecvError::Closed)) => return None,
+                        Err(..) => break web::Bytes::from_static(b": keep-alive\n\n")
+                    }
+                }
+            }
+        };
+        Some((Ok::<_, Infallible>(message), (changes, shutdown, state)))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(futures_util::StreamExt::chain(replay, live))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(change_events);
+}
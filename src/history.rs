@@ -0,0 +1,120 @@
+//! Every flush of a note by [`crate::DbThread`] records a revision of it in `note_revisions`,
+//! flushes within [`db::REVISION_COALESCE_WINDOW`] of the latest revision being recorded update it instead,
+//! so that a note that keeps being edited still gets a revision every window.
+
+use crate::*;
+
+#[derive(Serialize)]
+struct Revision {
+    rev: i64,
+    title: Box::<str>,
+    status: Box::<str>,
+    description: Box::<str>,
+    mod_time: UnixTimeStamp,
+    created_at: UnixTimeStamp,
+    updated_at: UnixTimeStamp,
+    /// Unified line diff of the description against the previous revision.
+    diff: String
+}
+
+pub fn record_revision(conn: &Connection, note: &Note) -> Result::<()> {
+    let uuid = note.uuid.to_string();
+    let now = unix_now();
+    let status = note.status.to_string();
+
+    let last = conn.query_row(
+        "SELECT rev, title, description, status, created_at FROM note_revisions WHERE note_uuid = ?1 ORDER BY rev DESC LIMIT 1",
+        params![uuid],
+        |row| Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, UnixTimeStamp>(4)?
+        ))
+    );
+
+    let rev = match last {
+        // e.g. the note got moved to the trash, nothing to record
+        Ok((_, ref title, ref description, ref last_status, _))
+        if **title == *note.title && **description == *note.description && *last_status == status => return Ok(()),
+
+        Ok((rev, .., created_at)) if now - created_at < db::REVISION_COALESCE_WINDOW.as_secs() as UnixTimeStamp => {
+            conn.execute(
+                "UPDATE note_revisions SET title = ?1, description = ?2, status = ?3, mod_time = ?4, updated_at = ?5 WHERE note_uuid = ?6 AND rev = ?7",
+                params![note.title, note.description, status, note.mod_time, now, uuid, rev]
+            )?;
+            return Ok(())
+        }
+
+        Ok((rev, ..)) => rev + 1,
+        Err(rusqlite::Error::QueryReturnedNoRows) => 1,
+        Err(e) => return Err(e)
+    };
+
+    conn.execute(
+        "INSERT INTO note_revisions (note_uuid, rev, title, description, status, mod_time, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+        params![uuid, rev, note.title, note.description, status, note.mod_time, now]
+    ).map(|_| ())
+}
+
+fn get_revisions(conn: &Connection, uuid: &Uuid) -> Result::<Vec::<Revision>> {
+    let mut stmt = conn.prepare(
+        "SELECT rev, title, description, status, mod_time, created_at, updated_at FROM note_revisions WHERE note_uuid = ?1 ORDER BY rev"
+    )?;
+    let mut prev_description = Box::<str>::default();
+    let mut revisions = stmt.query_map(params![uuid.to_string()], |row| {
+        Ok(Revision {
+            rev: row.get(0)?,
+            title: row.get(1)?,
+            description: row.get(2)?,
+            status: row.get(3)?,
+            mod_time: row.get(4)?,
+            created_at: row.get(5)?,
+            updated_at: row.get(6)?,
+            diff: String::new()
+        })
+    })?.map(|rev| rev.map(|mut rev| {
+        rev.diff = diffy::create_patch(&prev_description, &rev.description).to_string();
+        prev_description = Box::clone(&rev.description);
+        rev
+    })).collect::<Result::<Vec::<_>>>()?;
+    revisions.reverse();
+    Ok(revisions)
+}
+
+#[get("/notes/{uuid}/history")]
+async fn note_history(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_any_note_as(user, &uuid, Access::Read)?;
+    let revisions = get_revisions(&*state.pool.get()?, &uuid)?;
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[post("/notes/{uuid}/revert/{rev}")]
+async fn revert_note(state: Data::<Server>, user: User, path: web::Path::<(Uuid, i64)>) -> ApiResult {
+    let (uuid, rev) = path.into_inner();
+    state.get_note_as(user, &uuid, Access::Write)?;
+    let (title, description, status) = match state.pool.get()?.query_row(
+        "SELECT title, description, status FROM note_revisions WHERE note_uuid = ?1 AND rev = ?2",
+        params![uuid.to_string(), rev],
+        |row| Ok((row.get::<_, Box::<str>>(0)?, row.get::<_, Box::<str>>(1)?, Status(row.get(2)?)))
+    ) {
+        Ok(ok) => ok,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ApiError::NotFound),
+        Err(e) => return Err(e.into())
+    };
+    state.check_transition(&uuid, &status)?;
+    let note = state.modify_note(&uuid, |note| {
+        note.title = title;
+        note.status = status;
+        note.mod_time = unix_now();
+        note.description = description;
+    }).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(note_history)
+        .service(revert_note);
+}
@@ -0,0 +1,59 @@
+//! Safe wrappers around the encoders of [`crate::stb_image_write`], checking that the pixels add up to the
+//! dimensions they're given before handing them over.
+
+use crate::stb_image_write::*;
+
+/// Whether `image` holds exactly `width` by `height` pixels of `channels` bytes, with dimensions `stb` can take.
+#[inline]
+fn fits(image: &[u8], width: usize, height: usize, channels: usize) -> bool {
+    i32::try_from(width).is_ok() && i32::try_from(height).is_ok() &&
+        width.checked_mul(height).and_then(|pixels| pixels.checked_mul(channels)) == Some(image.len())
+}
+
+/// Collects what the encoders write into the `Vec` passed as their context.
+fn write(context: *mut u8, data: *mut u8, len: i32) {
+    // SAFETY: `context` is the `out` of the encoder calling this, and `data` points at `len` bytes written so far
+    let out = unsafe { &mut *(context as *mut Vec::<u8>) };
+    out.extend_from_slice(unsafe { std::slice::from_raw_parts(data, len as usize) })
+}
+
+/// Encodes 8-bit grayscale pixels as a PNG.
+pub fn write_png_to_memory(image: &[u8], width: usize, height: usize) -> Result::<Vec::<u8>, ()> {
+    if !fits(image, width, height, 1) { return Err(()) }
+    let mut out = Vec::new();
+    // SAFETY: `image` holds `width * height` bytes, just what a single channel image with rows `width` bytes apart
+    // takes, and `out` outlives the call
+    let ret = unsafe {
+        stbi_write_png_to_func(
+            write,
+            &mut out as *mut Vec::<u8> as *mut u8,
+            width as _,
+            height as _,
+            1,
+            image.as_ptr(),
+            width as _
+        )
+    };
+
+    if ret != 0 { Ok(out) } else { Err(()) }
+}
+
+/// Encodes 8-bit RGB pixels as a JPEG.
+pub fn write_jpg_to_memory(image: &[u8], width: usize, height: usize, quality: i32) -> Result::<Vec::<u8>, ()> {
+    if !fits(image, width, height, 3) { return Err(()) }
+    let mut out = Vec::new();
+    // SAFETY: `image` holds `width * height` RGB pixels, and `out` outlives the call
+    let ret = unsafe {
+        stbi_write_jpg_to_func(
+            write,
+            &mut out as *mut Vec::<u8> as *mut u8,
+            width as _,
+            height as _,
+            3,
+            image.as_ptr(),
+            quality
+        )
+    };
+
+    if ret != 0 { Ok(out) } else { Err(()) }
+}
@@ -0,0 +1,244 @@
+//! `[[Note title]]` or `[[<uuid>]]` in a description links to another note. Links are resolved and
+//! written to `note_links` by [`crate::DbThread`] on every flush, so backlinks lag behind edits by up to a flush.
+//! Only the links of notes changed since the last flush, or that might resolve differently because of them,
+//! are written again.
+//!
+//! Renaming a note rewrites `[[Old title]]` links pointing at it, so they keep pointing at it. Only links spelling
+//! out the old title exactly are rewritten, and only when neither title is shared with another note.
+
+use crate::*;
+
+use std::collections::{HashMap, HashSet};
+
+pub enum LinkTarget<'a> {
+    Uuid(Uuid),
+    Title(&'a str)
+}
+
+/// Iterates over the targets of all the `[[...]]` links in `description`.
+pub fn targets(description: &str) -> impl Iterator::<Item = LinkTarget<'_>> {
+    description.split("[[").skip(1).filter_map(|s| {
+        let (target, _) = s.split_once("]]")?;
+        let target = target.trim();
+        if target.is_empty() || target.contains(['\n', '[', ']']) { return None }
+        Some(match Uuid::parse_str(target) {
+            Ok(uuid) => LinkTarget::Uuid(uuid),
+            Err(..) => LinkTarget::Title(target)
+        })
+    })
+}
+
+/// Titles aren't unique, a title links to the most recently modified live note of the same owner that has it.
+fn titles(notes: &[Arc::<Note>]) -> HashMap::<(Option::<Uuid>, String), &Note> {
+    let mut titles = HashMap::<(Option::<Uuid>, String), &Note>::with_capacity(notes.len());
+    for note in notes.iter() {
+        titles.entry((note.owner, note.title.trim().to_lowercase()))
+            .and_modify(|other| if note.mod_time > other.mod_time { *other = note })
+            .or_insert(note);
+    }
+    titles
+}
+
+fn resolve(
+    target: &LinkTarget,
+    owner: Option::<Uuid>,
+    notes: &HashMap::<Uuid, &Note>,
+    titles: &HashMap::<(Option::<Uuid>, String), &Note>
+) -> Option::<Uuid> {
+    match target {
+        LinkTarget::Uuid(uuid) => notes.contains_key(uuid).then_some(*uuid),
+        LinkTarget::Title(title) => titles.get(&(owner, title.to_lowercase())).map(|note| note.uuid)
+    }
+}
+
+/// How a link target is kept in `note_link_targets`, whether it resolves or not, to find the links that a note
+/// changing might resolve differently.
+fn target_key(target: &LinkTarget) -> String {
+    match target {
+        LinkTarget::Uuid(uuid) => uuid.to_string(),
+        LinkTarget::Title(title) => title.to_lowercase()
+    }
+}
+
+/// Writes the links of `sources` afresh, leaving the links of every other note alone.
+fn save(conn: &Connection, notes: &Notes, sources: impl Iterator::<Item = Uuid>) -> Result::<()> {
+    let live = notes.iter().filter(|e| !e.is_trashed()).map(|e| Arc::clone(e.value())).collect::<Vec::<_>>();
+    let by_uuid = live.iter().map(|note| (note.uuid, &**note)).collect::<HashMap::<_, _>>();
+    let titles = titles(&live);
+
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut delete_links = tx.prepare("DELETE FROM note_links WHERE source_uuid = ?1")?;
+        let mut delete_targets = tx.prepare("DELETE FROM note_link_targets WHERE source_uuid = ?1")?;
+        let mut insert_link = tx.prepare(
+            "INSERT OR IGNORE INTO note_links (source_uuid, target_uuid) SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM notes WHERE uuid = ?1)"
+        )?;
+        let mut insert_target = tx.prepare(
+            "INSERT OR IGNORE INTO note_link_targets (source_uuid, target) SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM notes WHERE uuid = ?1)"
+        )?;
+        for uuid in sources {
+            let source = uuid.to_string();
+            delete_links.execute(params![source])?;
+            delete_targets.execute(params![source])?;
+            let Some(note) = by_uuid.get(&uuid) else { continue };
+            for target in targets(&note.description) {
+                insert_target.execute(params![source, target_key(&target)])?;
+                match resolve(&target, note.owner, &by_uuid, &titles) {
+                    Some(target) if target != note.uuid => _ = insert_link.execute(params![source, target.to_string()])?,
+                    _ => {}
+                }
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// Rewrites the links of all the notes, done once on startup.
+pub fn rebuild(conn: &Connection, notes: &Notes) -> Result::<()> {
+    conn.execute("DELETE FROM note_links", [])?;
+    conn.execute("DELETE FROM note_link_targets", [])?;
+    save(conn, notes, notes.iter().map(|e| *e.key()))
+}
+
+/// Rewrites the links of the `changed` notes, and of the notes whose links might now resolve differently because of
+/// them: the ones that linked to them, or that link to their UUID or current title.
+pub fn update(conn: &Connection, notes: &Notes, changed: &[Uuid]) -> Result::<()> {
+    if changed.is_empty() { return Ok(()) }
+    let mut sources = changed.iter().copied().collect::<HashSet::<_>>();
+    {
+        let mut linked = conn.prepare("SELECT source_uuid FROM note_links WHERE target_uuid = ?1")?;
+        let mut targeted = conn.prepare("SELECT source_uuid FROM note_link_targets WHERE target = ?1")?;
+        let parse = |row: &rusqlite::Row| Ok(Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"));
+        for uuid in changed.iter() {
+            let title = notes.get(uuid).map(|note| note.title.trim().to_lowercase());
+            let uuid = uuid.to_string();
+            for source in linked.query_map(params![uuid], parse)? { sources.insert(source?); }
+            for key in std::iter::once(uuid).chain(title) {
+                for source in targeted.query_map(params![key], parse)? { sources.insert(source?); }
+            }
+        }
+    }
+    save(conn, notes, sources.into_iter())
+}
+
+/// Whether `other` is a live note of the same owner as `note` with the same `title`, so that a link to `title`
+/// might mean either of them.
+fn clashes(other: &Note, note: &Note, title: &str) -> bool {
+    !other.is_trashed() && other.uuid != note.uuid && other.owner == note.owner && other.title.trim().to_lowercase() == title.to_lowercase()
+}
+
+/// `description` with every `[[old]]` link turned into `[[new_title]]`.
+fn rewrite(description: &str, old: &str, new_title: &str) -> String {
+    let mut rewritten = String::with_capacity(description.len());
+    let mut rest = description;
+    while let Some(start) = rest.find("[[") {
+        rewritten.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match rest[2..].split_once("]]") {
+            Some((target, after)) if target.trim() == old => {
+                rewritten.push_str("[[");
+                rewritten.push_str(new_title);
+                rewritten.push_str("]]");
+                rest = after
+            }
+            _ => {
+                rewritten.push_str("[[");
+                rest = &rest[2..]
+            }
+        }
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Points `[[old title]]` links at the new title of `note`, as long as both titles are its alone, so that a link
+/// never ends up pointing at some other note. Only notes of the same owner are touched, the notes of others are
+/// theirs to edit.
+pub fn rename(state: &Server, note: &Note, old_title: &str) {
+    let old = old_title.trim();
+    let new_title = note.title.trim();
+    if old.is_empty() || new_title.is_empty() || new_title.contains(['\n', '[', ']']) { return }
+    let taken = |title: &str| state.notes.iter().any(|e| clashes(&e, note, title));
+    if taken(old) || taken(new_title) { return }
+
+    let links_to_old = |description: &str| targets(description).any(|target| {
+        matches!(target, LinkTarget::Title(title) if title == old)
+    });
+    state.modify_notes_where(|other| other.uuid != note.uuid && other.owner == note.owner && links_to_old(&other.description), |other| {
+        other.description = rewrite(&other.description, old, new_title).into()
+    });
+}
+
+#[get("/notes/{uuid}/backlinks")]
+async fn backlinks(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_note_as(user, &uuid, Access::Read)?;
+    let conn = state.pool.get()?;
+    let mut stmt = conn.prepare("SELECT source_uuid FROM note_links WHERE target_uuid = ?1")?;
+    let sources = stmt.query_map(params![uuid.to_string()], |row| row.get::<_, String>(0))?
+        .collect::<Result::<Vec::<_>>>()?;
+    let mut notes = sources.iter()
+        .filter_map(|source| Uuid::parse_str(source).ok())
+        .filter_map(|source| state.get_note(&source))
+        .filter(|source| state.can_read(user, source))
+        .collect::<Vec::<_>>();
+    ordering::sort(&mut notes);
+    Ok(HttpResponse::Ok().json(notes))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(backlinks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(title: &str, owner: Option::<Uuid>, mod_time: UnixTimeStamp) -> Arc::<Note> {
+        Arc::new(Note { uuid: Uuid::new_v4(), title: title.into(), owner, mod_time, ..Note::default() })
+    }
+
+    #[test]
+    fn finds_link_targets() {
+        let uuid = Uuid::new_v4();
+        let description = format!("[[ Groceries ]], [[{uuid}]] [[]] [[  ]] [[a\nb]] [[[nested]]] [[unterminated");
+        let targets = targets(&description).map(|target| match target {
+            LinkTarget::Uuid(uuid) => uuid.to_string(),
+            LinkTarget::Title(title) => title.to_owned()
+        }).collect::<Vec::<_>>();
+        assert_eq!(targets, ["Groceries".to_owned(), uuid.to_string()]);
+    }
+
+    #[test]
+    fn titles_resolve_to_the_latest_note_of_the_owner() {
+        let (alice, bob) = (Some(Uuid::new_v4()), Some(Uuid::new_v4()));
+        let notes = [note("Plans", alice, 1), note("plans ", alice, 3), note("Plans", alice, 2), note("Plans", bob, 4)];
+        let by_uuid = notes.iter().map(|note| (note.uuid, &**note)).collect::<HashMap::<_, _>>();
+        let titles = titles(&notes);
+        assert_eq!(resolve(&LinkTarget::Title("PLANS"), alice, &by_uuid, &titles), Some(notes[1].uuid));
+        assert_eq!(resolve(&LinkTarget::Title("plans"), bob, &by_uuid, &titles), Some(notes[3].uuid));
+        assert_eq!(resolve(&LinkTarget::Title("plans"), None, &by_uuid, &titles), None);
+        assert_eq!(resolve(&LinkTarget::Uuid(notes[3].uuid), alice, &by_uuid, &titles), Some(notes[3].uuid));
+        assert_eq!(resolve(&LinkTarget::Uuid(Uuid::new_v4()), alice, &by_uuid, &titles), None);
+    }
+
+    #[test]
+    fn titles_clash_with_live_notes_of_the_same_owner() {
+        let (alice, bob) = (Some(Uuid::new_v4()), Some(Uuid::new_v4()));
+        let renamed = note("New", alice, 1);
+        assert!(clashes(&note(" new", alice, 1), &renamed, "NEW"));
+        assert!(clashes(&note("Old", alice, 1), &renamed, "old"));
+        assert!(!clashes(&renamed, &renamed, "New"));
+        assert!(!clashes(&note("New", bob, 1), &renamed, "New"));
+        assert!(!clashes(&note("Other", alice, 1), &renamed, "New"));
+        let trashed = Note { deleted_at: Some(1), ..Note::clone(&note("New", alice, 1)) };
+        assert!(!clashes(&trashed, &renamed, "New"));
+    }
+
+    #[test]
+    fn rewrites_links_spelling_out_the_old_title() {
+        assert_eq!(rewrite("see [[Old]] and [[ Old ]], [[old]]", "Old", "New"), "see [[New]] and [[New]], [[old]]");
+        assert_eq!(rewrite("[[Older]] [[Old", "Old", "New"), "[[Older]] [[Old");
+        assert_eq!(rewrite("[[[[Old]]]]", "Old", "New"), "[[[[New]]]]");
+        assert_eq!(rewrite("ünï [[Öld]]!", "Öld", "Nëw"), "ünï [[Nëw]]!");
+    }
+}
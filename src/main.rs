@@ -1,32 +1,106 @@
+#![allow(clippy::toplevel_ref_arg)]
+
 use std::time::Duration;
-use std::sync::{Arc, Mutex};
-use std::net::{IpAddr, UdpSocket};
+use std::sync::{Arc, Mutex, RwLock};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::sync::atomic::{Ordering, AtomicBool, AtomicUsize};
+use std::sync::atomic::{Ordering, AtomicBool, AtomicUsize, AtomicU64};
 
 use uuid::Uuid;
-use dashmap::DashMap;
+use dashmap::{DashMap, Entry};
 use serde_json::json;
 use actix_rt::signal;
 use actix_files::Files;
 use actix_rt::task::JoinHandle;
 use qrcodegen::{QrCode, QrCodeEcc};
-use derive_more::{Display, FromStr};
+use derive_more::Display;
 use serde::{Serialize, Deserialize};
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{params, Result, Connection};
 use actix_web::{
-    get, put, post, delete, rt as actix_rt,
-    App, HttpServer, HttpResponse, Responder,
-    middleware::Logger, web::{self, Data, Json}
+    get, put, post, patch, delete, rt as actix_rt,
+    App, HttpServer, HttpRequest, HttpResponse, Responder,
+    middleware::{self, Logger}, web::{self, Data, Json},
+    http::header::{self, HeaderName, HeaderValue}
 };
 
 mod qr;
 use qr::*;
 
-#[allow(unused_imports, unused_parens, non_camel_case_types, unused_mut, dead_code, unused_assignments, unused_variables, static_mut_refs, non_snake_case, non_upper_case_globals)]
+mod image_write;
+
+mod api;
+use api::{ApiError, ApiResult};
+
+mod config;
+use config::Config;
+
+mod validate;
+use validate::Validate;
+
+mod trash;
+
+mod history;
+
+mod tags;
+
+mod notebooks;
+
+mod statuses;
+use statuses::Workflow;
+
+mod board;
+
+mod ordering;
+
+mod checklist;
+use checklist::ChecklistItem;
+
+mod markdown;
+
+mod links;
+
+mod reminders;
+use reminders::Reminder;
+
+mod recurrence;
+use recurrence::Recurrence;
+
+mod templates;
+use templates::TemplateQuery;
+
+mod attachments;
+
+mod thumbnails;
+
+mod events;
+use events::ChangeKind;
+
+mod collab;
+
+mod sync;
+use sync::ChangeLog;
+
+mod auth;
+
+mod users;
+use users::User;
+
+mod sharing;
+use sharing::Access;
+
+mod share_links;
+
+mod tls;
+
+mod rate_limit;
+
+#[allow(clippy::all, unused_imports, unused_parens, non_camel_case_types, unused_mut, dead_code, unused_assignments, unused_variables, static_mut_refs, non_snake_case, non_upper_case_globals)]
 mod stb_image_write;
 
 const PORT: u16 = 6969;
+/// How long the pairing token of a QR code has to stay valid for it to be shown again, see [`Server::pairing_qr`].
+const QR_MIN_TTL: Duration = Duration::from_secs(60);
 
 macro_rules! atomic_type {
     ($(type $name: ident = $ty: ty;)*) => {$(paste::paste! {
@@ -44,6 +118,16 @@ atomic_type! {
 
 mod json {
     use super::Deserialize;
+    use serde::Deserializer;
+
+    /// Tells an absent field (`None`) apart from an explicit `null` (`Some(None)`).
+    pub fn double_option<'de, T, D>(de: D) -> Result::<Option::<Option::<T>>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>
+    {
+        Option::<T>::deserialize(de).map(Some)
+    }
 
     #[derive(Deserialize)]
     pub struct Uuid { pub uuid: super::Uuid }
@@ -56,14 +140,83 @@ mod json {
         pub mod_time: super::UnixTimeStamp,
         pub description: Box::<str>
     }
+
+    #[derive(Deserialize)]
+    pub struct NoteBody {
+        pub title: Box::<str>,
+        #[serde(default)]
+        pub status: super::Status,
+        #[serde(default = "super::unix_now")]
+        pub mod_time: super::UnixTimeStamp,
+        pub description: Box::<str>,
+        #[serde(default)]
+        pub tags: Vec::<Box::<str>>,
+        #[serde(default)]
+        pub pinned: bool,
+        #[serde(default)]
+        pub notebook: Option::<super::Uuid>,
+        #[serde(default)]
+        pub due_at: Option::<super::UnixTimeStamp>,
+        #[serde(default)]
+        pub remind_at: Option::<super::UnixTimeStamp>,
+        #[serde(default)]
+        pub recurrence: Option::<super::Recurrence>
+    }
+
+    impl NoteBody {
+        /// Replaces every field of `note` that the body carries.
+        pub fn apply(self, note: &mut super::Note) {
+            note.title = self.title;
+            note.status = self.status;
+            note.mod_time = self.mod_time;
+            note.description = self.description;
+            note.tags = self.tags;
+            note.pinned = self.pinned;
+            note.notebook = self.notebook;
+            note.due_at = self.due_at;
+            note.set_remind_at(self.remind_at);
+            note.recurrence = self.recurrence;
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct NotePatch {
+        pub title: Option::<Box::<str>>,
+        pub status: Option::<super::Status>,
+        pub mod_time: Option::<super::UnixTimeStamp>,
+        pub description: Option::<Box::<str>>,
+        pub tags: Option::<Vec::<Box::<str>>>,
+        pub pinned: Option::<bool>,
+        #[serde(default, deserialize_with = "double_option")]
+        pub notebook: Option::<Option::<super::Uuid>>,
+        #[serde(default, deserialize_with = "double_option")]
+        pub due_at: Option::<Option::<super::UnixTimeStamp>>,
+        #[serde(default, deserialize_with = "double_option")]
+        pub remind_at: Option::<Option::<super::UnixTimeStamp>>,
+        #[serde(default, deserialize_with = "double_option")]
+        pub recurrence: Option::<Option::<super::Recurrence>>
+    }
+
+    #[derive(Deserialize)]
+    pub struct Name { pub name: Box::<str> }
 }
 
-#[repr(u8)]
-#[derive(Clone, Debug, FromStr, Display, Serialize, Deserialize)]
-enum Status {
-    Active,
-    Archived,
-    Completed,
+#[inline]
+fn unix_now() -> UnixTimeStamp {
+    DbThread::curr_time().as_secs() as _
+}
+
+/// Name of one of the statuses defined in the `statuses` table, see [`statuses::Workflow`].
+/// Notes may still carry a status that has been removed since, so this is never assumed to be valid.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Display, Serialize, Deserialize)]
+#[serde(transparent)]
+struct Status(Box::<str>);
+
+impl Status {
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 #[repr(u8)]
@@ -75,73 +228,403 @@ enum NoteDbStatus {
     Updated,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 struct Note {
     #[serde(skip_deserializing)]
     uuid: Uuid,
     #[serde(skip)]
     db_status: NoteDbStatus,
+    /// Only optional when the note is made from a template, see [`templates`].
+    #[serde(default)]
     title: Box::<str>,
+    /// Defaults to the first status of the workflow if left out.
+    #[serde(default)]
     status: Status,
+    #[serde(default = "unix_now")]
     mod_time: UnixTimeStamp,
+    #[serde(default)]
     description: Box::<str>,
+    /// Sanitized HTML rendering of `description`, see [`markdown`].
+    #[serde(skip_deserializing)]
+    description_html: Box::<str>,
+    /// Set while the note sits in the trash.
+    #[serde(skip_deserializing)]
+    deleted_at: Option::<UnixTimeStamp>,
+    #[serde(default)]
+    tags: Vec::<Box::<str>>,
+    #[serde(default)]
+    notebook: Option::<Uuid>,
+    /// Order of the note within its status column on the board, see [`board`].
+    #[serde(skip_deserializing)]
+    column_position: f64,
+    /// Pinned notes are listed before all the others.
+    #[serde(default)]
+    pinned: bool,
+    /// Order of the note in the listing, see [`ordering`].
+    #[serde(skip_deserializing)]
+    position: f64,
+    /// Serialized as just the progress, the items themselves are served by [`checklist`].
+    #[serde(rename = "checklist", serialize_with = "checklist::serialize_progress", skip_deserializing)]
+    items: Vec::<ChecklistItem>,
+    #[serde(default)]
+    due_at: Option::<UnixTimeStamp>,
+    /// When to fire a reminder about the note, see [`reminders`].
+    #[serde(default)]
+    remind_at: Option::<UnixTimeStamp>,
+    /// When the reminder at `remind_at` has been fired, so that it fires only once.
+    #[serde(skip_deserializing)]
+    reminded_at: Option::<UnixTimeStamp>,
+    /// Completing the note spawns its next occurrence, see [`recurrence`].
+    #[serde(default)]
+    recurrence: Option::<Recurrence>,
+    /// Position of the latest change to the note in the server-wide sequence, see [`sync`].
+    #[serde(skip_deserializing)]
+    seq: u64,
+    /// The user the note belongs to, only `None` for notes made before there were any, see [`users`].
+    #[serde(skip_deserializing)]
+    owner: Option::<Uuid>,
+}
+
+impl Note {
+    #[inline(always)]
+    fn is_trashed(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    #[inline(always)]
+    fn render_description(&mut self) {
+        self.description_html = markdown::render(&self.description)
+    }
+
+    /// Rescheduling the reminder re-arms it.
+    #[inline]
+    fn set_remind_at(&mut self, remind_at: Option::<UnixTimeStamp>) {
+        if self.remind_at != remind_at { self.reminded_at = None }
+        self.remind_at = remind_at
+    }
 }
 
 #[repr(transparent)]
 struct Db(Connection);
 
+/// Connections for handlers that query the DB directly, the notes themselves are written by [`DbThread`] only.
+type DbPool = r2d2::Pool::<SqliteConnectionManager>;
+
 mod db {
     use std::time::Duration;
 
     pub type Result = rusqlite::Result::<usize>;
 
+    /// Executed on every opened connection, the flushing connection and the pooled ones are used concurrently.
+    pub const CONNECTION_INIT: &str = "PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000; PRAGMA foreign_keys = ON;";
+
     pub const FILE_PATH: &str = "internotes.db";
+    /// Where the contents of attachments are kept, see [`crate::attachments`].
+    pub const ATTACHMENTS_DIR: &str = "attachments";
     pub const DB_INSERTION_NOTES_COUNT_THRESHOLD: usize = 5;
     pub const DB_INSERTION_DURATION_THRESHOLD: Duration = Duration::from_secs(15);
+    pub const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+    /// Flushes of a note within this long after its latest revision was recorded are folded into that revision.
+    pub const REVISION_COALESCE_WINDOW: Duration = Duration::from_secs(2 * 60);
+
+    /// Schema migrations, applied in order. The index of the last applied one (+1) is kept in `PRAGMA user_version`,
+    /// so never edit or reorder existing entries, only append new ones.
+    pub const MIGRATIONS: &[&str] = &[
+        "CREATE TABLE IF NOT EXISTS notes (
+            uuid        TEXT PRIMARY KEY,
+            title       TEXT NOT NULL,
+            description TEXT NOT NULL,
+            status      TEXT NOT NULL,
+            mod_time    INTEGER NOT NULL
+        )",
+        "ALTER TABLE notes ADD COLUMN deleted_at INTEGER",
+        "CREATE TABLE note_revisions (
+            note_uuid   TEXT NOT NULL,
+            rev         INTEGER NOT NULL,
+            title       TEXT NOT NULL,
+            description TEXT NOT NULL,
+            status      TEXT NOT NULL,
+            mod_time    INTEGER NOT NULL,
+            created_at  INTEGER NOT NULL,
+            updated_at  INTEGER NOT NULL,
+            PRIMARY KEY (note_uuid, rev)
+        )",
+        "CREATE TABLE notebooks (
+            uuid TEXT PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE
+        );
+        CREATE TABLE tags (
+            id   INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE
+        );
+        CREATE TABLE note_tags (
+            note_uuid TEXT NOT NULL REFERENCES notes(uuid) ON DELETE CASCADE,
+            tag_id    INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            PRIMARY KEY (note_uuid, tag_id)
+        );
+        ALTER TABLE notes ADD COLUMN notebook TEXT REFERENCES notebooks(uuid) ON DELETE SET NULL;",
+        "CREATE TABLE statuses (
+            name     TEXT PRIMARY KEY,
+            position INTEGER NOT NULL,
+            color    TEXT NOT NULL,
+            done     INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE status_transitions (
+            from_status TEXT NOT NULL REFERENCES statuses(name) ON UPDATE CASCADE ON DELETE CASCADE,
+            to_status   TEXT NOT NULL REFERENCES statuses(name) ON UPDATE CASCADE ON DELETE CASCADE,
+            PRIMARY KEY (from_status, to_status)
+        );
+        INSERT INTO statuses (name, position, color, done) VALUES
+            ('Active', 0, '#007bff', 0),
+            ('Archived', 1, '#6c757d', 0),
+            ('Completed', 2, '#28a745', 1);",
+        "ALTER TABLE notes ADD COLUMN column_position REAL NOT NULL DEFAULT 0",
+        // keep the listing in the order it used to be derived from
+        "ALTER TABLE notes ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE notes ADD COLUMN position REAL NOT NULL DEFAULT 0;
+        UPDATE notes SET position = -mod_time;",
+        "CREATE TABLE checklist_items (
+            id        TEXT PRIMARY KEY,
+            note_uuid TEXT NOT NULL REFERENCES notes(uuid) ON DELETE CASCADE,
+            text      TEXT NOT NULL,
+            checked   INTEGER NOT NULL DEFAULT 0,
+            position  INTEGER NOT NULL
+        );
+        CREATE INDEX checklist_items_note_uuid ON checklist_items(note_uuid);",
+        "CREATE TABLE note_links (
+            source_uuid TEXT NOT NULL REFERENCES notes(uuid) ON DELETE CASCADE,
+            target_uuid TEXT NOT NULL,
+            PRIMARY KEY (source_uuid, target_uuid)
+        );
+        CREATE INDEX note_links_target_uuid ON note_links(target_uuid);",
+        "ALTER TABLE notes ADD COLUMN due_at INTEGER;
+        ALTER TABLE notes ADD COLUMN remind_at INTEGER;
+        ALTER TABLE notes ADD COLUMN reminded_at INTEGER;",
+        "ALTER TABLE notes ADD COLUMN recurrence TEXT",
+        "CREATE TABLE templates (
+            uuid        TEXT PRIMARY KEY,
+            name        TEXT NOT NULL UNIQUE,
+            title       TEXT NOT NULL,
+            description TEXT NOT NULL,
+            tags        TEXT NOT NULL DEFAULT '[]',
+            status      TEXT REFERENCES statuses(name) ON UPDATE CASCADE ON DELETE SET NULL,
+            notebook    TEXT REFERENCES notebooks(uuid) ON DELETE SET NULL,
+            counter     INTEGER NOT NULL DEFAULT 0
+        )",
+        // no foreign key on `note_uuid`, files can be attached before the note reaches the DB
+        "CREATE TABLE attachments (
+            id           TEXT PRIMARY KEY,
+            note_uuid    TEXT NOT NULL,
+            file_name    TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            size         INTEGER NOT NULL,
+            hash         TEXT NOT NULL,
+            created_at   INTEGER NOT NULL
+        );
+        CREATE INDEX attachments_note_uuid ON attachments(note_uuid);
+        CREATE INDEX attachments_hash ON attachments(hash);",
+        // every existing note counts as changed once
+        "ALTER TABLE notes ADD COLUMN seq INTEGER NOT NULL DEFAULT 0;
+        UPDATE notes SET seq = rowid;
+        CREATE TABLE tombstones (
+            uuid TEXT PRIMARY KEY,
+            seq  INTEGER NOT NULL
+        );",
+        "CREATE TABLE devices (
+            id         TEXT PRIMARY KEY,
+            name       TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at INTEGER NOT NULL
+        )",
+        // devices paired before there were accounts have to log in again,
+        // and there's no foreign key on `note_uuid` since notes can be shared before they reach the DB
+        "CREATE TABLE users (
+            id            TEXT PRIMARY KEY,
+            name          TEXT NOT NULL UNIQUE,
+            password_hash TEXT NOT NULL,
+            created_at    INTEGER NOT NULL
+        );
+        ALTER TABLE notes ADD COLUMN owner TEXT;
+        DROP TABLE devices;
+        CREATE TABLE devices (
+            id         TEXT PRIMARY KEY,
+            user_id    TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name       TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE note_shares (
+            note_uuid  TEXT NOT NULL,
+            user_id    TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            permission TEXT NOT NULL,
+            PRIMARY KEY (note_uuid, user_id)
+        );",
+        // notebook and template names are unique per owner now, which takes rebuilding their tables. dropping
+        // `notebooks` takes the notes out of them, so which notebook every note was in is put back afterwards
+        "CREATE TEMP TABLE notebook_refs AS SELECT uuid, notebook FROM notes WHERE notebook IS NOT NULL;
+        CREATE TEMP TABLE template_rows AS SELECT * FROM templates;
+        CREATE TABLE notebooks_owned (
+            uuid  TEXT PRIMARY KEY,
+            owner TEXT REFERENCES users(id) ON DELETE CASCADE,
+            name  TEXT NOT NULL,
+            UNIQUE (owner, name)
+        );
+        INSERT INTO notebooks_owned (uuid, name) SELECT uuid, name FROM notebooks;
+        DROP TABLE templates;
+        DROP TABLE notebooks;
+        ALTER TABLE notebooks_owned RENAME TO notebooks;
+        UPDATE notes SET notebook = (SELECT notebook FROM notebook_refs WHERE notebook_refs.uuid = notes.uuid)
+        WHERE uuid IN (SELECT uuid FROM notebook_refs);
+        CREATE TABLE templates (
+            uuid        TEXT PRIMARY KEY,
+            owner       TEXT REFERENCES users(id) ON DELETE CASCADE,
+            name        TEXT NOT NULL,
+            title       TEXT NOT NULL,
+            description TEXT NOT NULL,
+            tags        TEXT NOT NULL DEFAULT '[]',
+            status      TEXT REFERENCES statuses(name) ON UPDATE CASCADE ON DELETE SET NULL,
+            notebook    TEXT REFERENCES notebooks(uuid) ON DELETE SET NULL,
+            counter     INTEGER NOT NULL DEFAULT 0,
+            UNIQUE (owner, name)
+        );
+        INSERT INTO templates (uuid, name, title, description, tags, status, notebook, counter)
+        SELECT uuid, name, title, description, tags, status, notebook, counter FROM template_rows;
+        DROP TABLE notebook_refs;
+        DROP TABLE template_rows;
+        CREATE TABLE notebook_shares (
+            notebook_uuid TEXT NOT NULL REFERENCES notebooks(uuid) ON DELETE CASCADE,
+            user_id       TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            permission    TEXT NOT NULL,
+            PRIMARY KEY (notebook_uuid, user_id)
+        );",
+        "CREATE TABLE share_links (
+            id         TEXT PRIMARY KEY,
+            note_uuid  TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER
+        );
+        CREATE INDEX share_links_note_uuid ON share_links(note_uuid);",
+        "DELETE FROM note_revisions WHERE note_uuid NOT IN (SELECT uuid FROM notes)",
+        "CREATE TABLE note_link_targets (
+            source_uuid TEXT NOT NULL REFERENCES notes(uuid) ON DELETE CASCADE,
+            target      TEXT NOT NULL,
+            PRIMARY KEY (source_uuid, target)
+        );
+        CREATE INDEX note_link_targets_target ON note_link_targets(target);",
+        // tombstones made before this don't tell whose note it was, and go to nobody
+        "ALTER TABLE tombstones ADD COLUMN owner TEXT;",
+        // notes users stopped seeing, other than their own ones being removed
+        "CREATE TABLE revocations (
+            note_uuid TEXT NOT NULL,
+            user_id   TEXT NOT NULL,
+            seq       INTEGER NOT NULL,
+            PRIMARY KEY (note_uuid, user_id)
+        );",
+    ];
 }
 
 impl Db {
     #[inline]
     fn new() -> Self {
         use db::FILE_PATH;
-        let conn = match Connection::open(FILE_PATH) {
+        let conn = match Connection::open(FILE_PATH).and_then(|conn| {
+            conn.execute_batch(db::CONNECTION_INIT).map(|_| conn)
+        }) {
             Ok(ok) => ok,
             Err(e) => panic!("could not open database file: {FILE_PATH}: {e}")
         };
-        Db(conn)
+        let mut db = Db(conn);
+        if let Err(e) = db.migrate() {
+            panic!("could not migrate database file: {FILE_PATH}: {e}")
+        }
+        db
+    }
+
+    #[inline]
+    fn pool() -> DbPool {
+        use db::FILE_PATH;
+        let manager = SqliteConnectionManager::file(FILE_PATH).with_init(|conn| conn.execute_batch(db::CONNECTION_INIT));
+        match r2d2::Pool::new(manager) {
+            Ok(ok) => ok,
+            Err(e) => panic!("could not open database file: {FILE_PATH}: {e}")
+        }
+    }
+
+    fn migrate(&mut self) -> Result::<()> {
+        let conn = &mut self.0;
+        let version = conn.query_row("PRAGMA user_version", [], |row| row.get::<_, usize>(0))?;
+        for (i, migration) in db::MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?
+        }
+        Ok(())
     }
 
     fn get_notes(&self) -> Result::<Notes> {
         let ref conn = self.0;
-        let mut stmt = conn.prepare("SELECT uuid, title, description, status, mod_time FROM notes")?;
+        let mut stmt = conn.prepare("SELECT uuid, title, description, status, mod_time, deleted_at, notebook, column_position, pinned, position, due_at, remind_at, reminded_at, recurrence, seq, owner FROM notes")?;
         let notes = stmt.query_map([], |row| {
             let uuid = Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID");
+            let description = row.get::<_, Box::<str>>(2)?;
             Ok((Uuid::clone(&uuid), Arc::new(Note {
                 uuid,
                 db_status: NoteDbStatus::FromDb,
                 title: row.get(1)?,
-                description: row.get(2)?,
-                status: Status::from_str(&row.get::<_, String>(3)?).unwrap(),
-                mod_time: row.get(4)?
+                description_html: markdown::render(&description),
+                description,
+                status: Status(row.get(3)?),
+                mod_time: row.get(4)?,
+                deleted_at: row.get(5)?,
+                tags: Vec::new(),
+                notebook: row.get::<_, Option::<String>>(6)?.map(|uuid| Uuid::parse_str(&uuid).expect("invalid UUID")),
+                column_position: row.get(7)?,
+                pinned: row.get(8)?,
+                position: row.get(9)?,
+                items: Vec::new(),
+                due_at: row.get(10)?,
+                remind_at: row.get(11)?,
+                reminded_at: row.get(12)?,
+                // a rule that doesn't parse anymore shouldn't keep the server from starting
+                recurrence: row.get::<_, Option::<String>>(13)?.and_then(|rule| rule.parse().map_err(|e| {
+                    eprintln!("[WARN] note {uuid} has invalid recurrence rule {rule:?}, ignoring it: {e}")
+                }).ok()),
+                seq: row.get(14)?,
+                owner: row.get::<_, Option::<String>>(15)?.map(|uuid| Uuid::parse_str(&uuid).expect("invalid UUID"))
             })))
         })?.collect::<Result::<_, _>>()?;
+        tags::load_note_tags(conn, &notes)?;
+        checklist::load_note_items(conn, &notes)?;
         Ok(notes)
     }
 
     #[inline]
     fn insert_notes(&self, notes: &Notes) -> Vec::<db::Result> {
         let ref conn = self.0;
-        notes.iter().filter(|e| e.db_status == NoteDbStatus::New).map(|e| {
-            conn.execute(
-                "INSERT INTO notes (uuid, title, description, status, mod_time) VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![e.uuid.to_string(), e.title, e.description, e.status.to_string(), e.mod_time]
-            )
+        notes.iter_mut().filter(|e| e.db_status == NoteDbStatus::New).map(|mut e| {
+            let ret = conn.execute(
+                "INSERT INTO notes (uuid, title, description, status, mod_time, deleted_at, notebook, column_position, pinned, position, due_at, remind_at, reminded_at, recurrence, seq, owner) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                params![e.uuid.to_string(), e.title, e.description, e.status.to_string(), e.mod_time, e.deleted_at, e.notebook.map(|u| u.to_string()), e.column_position, e.pinned, e.position, e.due_at, e.remind_at, e.reminded_at, e.recurrence.as_ref().map(Recurrence::to_string), e.seq, e.owner.map(|u| u.to_string())]
+            ).and_then(|ret| {
+                tags::save_note_tags(conn, &e)?;
+                checklist::save_note_items(conn, &e)?;
+                Ok(ret)
+            });
+            if ret.is_ok() {
+                if let Err(err) = history::record_revision(conn, &e) {
+                    eprintln!("could not record revision of note with uuid: {uuid}: {err}", uuid = e.uuid)
+                }
+                Arc::make_mut(e.value_mut()).db_status = NoteDbStatus::FromDb
+            }
+            ret
         }).collect()
     }
 
     fn update_notes(&self, notes: &Notes) -> Vec::<db::Result> {
         let ref conn = self.0;
-        notes.iter().filter(|e| e.db_status == NoteDbStatus::Updated).filter(|e| {
+        notes.iter_mut().filter(|e| e.db_status == NoteDbStatus::Updated).filter(|e| {
             match conn.query_row(
                 "SELECT 1 FROM notes WHERE uuid = ?1 LIMIT 1",
                 params![e.uuid.to_string()],
@@ -154,23 +637,56 @@ impl Db {
                     false
                 }
             }
-        }).map(|e| {
-            conn.execute(
-                "UPDATE notes SET title = ?1, description = ?2, status = ?3, mod_time = ?4 WHERE uuid = ?5",
-                params![e.title, e.description, e.status.to_string(), e.mod_time, e.uuid.to_string()],
-            )
+        }).map(|mut e| {
+            let ret = conn.execute(
+                "UPDATE notes SET title = ?1, description = ?2, status = ?3, mod_time = ?4, deleted_at = ?5, notebook = ?6, column_position = ?7, pinned = ?8, position = ?9, due_at = ?10, remind_at = ?11, reminded_at = ?12, recurrence = ?13, seq = ?14, owner = ?15 WHERE uuid = ?16",
+                params![e.title, e.description, e.status.to_string(), e.mod_time, e.deleted_at, e.notebook.map(|u| u.to_string()), e.column_position, e.pinned, e.position, e.due_at, e.remind_at, e.reminded_at, e.recurrence.as_ref().map(Recurrence::to_string), e.seq, e.owner.map(|u| u.to_string()), e.uuid.to_string()],
+            ).and_then(|ret| {
+                tags::save_note_tags(conn, &e)?;
+                checklist::save_note_items(conn, &e)?;
+                Ok(ret)
+            });
+            if ret.is_ok() {
+                if let Err(err) = history::record_revision(conn, &e) {
+                    eprintln!("could not record revision of note with uuid: {uuid}: {err}", uuid = e.uuid)
+                }
+                Arc::make_mut(e.value_mut()).db_status = NoteDbStatus::FromDb
+            }
+            ret
         }).collect()
     }
 
     #[inline]
     fn remove_notes(&self, removed_notes: &AtomicRemovedNotes) -> Vec::<db::Result> {
         let ref conn = self.0;
-        removed_notes.lock().unwrap().iter().map(|e| e.uuid.to_string()).map(|uuid| {
+        removed_notes.lock().unwrap().drain(..).map(|e| {
+            let uuid = e.uuid.to_string();
+            attachments::remove_note_attachments(conn, &uuid)?;
+            // everyone it was shared with gets to hear it's gone, along with the owner
+            conn.execute(
+                "INSERT OR REPLACE INTO revocations (note_uuid, user_id, seq)
+                SELECT ?1, user_id, ?2 FROM note_shares WHERE note_uuid = ?1
+                UNION SELECT ?1, user_id, ?2 FROM notebook_shares WHERE notebook_uuid = ?3 AND user_id IS NOT ?4",
+                params![uuid, e.seq, e.notebook.map(|u| u.to_string()), e.owner.map(|u| u.to_string())]
+            )?;
+            conn.execute("DELETE FROM note_shares WHERE note_uuid = ?1", params![uuid])?;
+            conn.execute("DELETE FROM share_links WHERE note_uuid = ?1", params![uuid])?;
+            conn.execute("DELETE FROM note_revisions WHERE note_uuid = ?1", params![uuid])?;
+            conn.execute(
+                "INSERT OR REPLACE INTO tombstones (uuid, seq, owner) VALUES (?1, ?2, ?3)",
+                params![uuid, e.seq, e.owner.map(|owner| owner.to_string())]
+            )?;
             conn.execute("DELETE FROM notes WHERE uuid = ?1", params![uuid])
         }).collect()
     }
 
     fn update(&self, notes: &Notes, removed_notes: &AtomicRemovedNotes) {
+        let changed = notes.iter()
+            .filter(|e| e.db_status != NoteDbStatus::FromDb)
+            .map(|e| *e.key())
+            .chain(removed_notes.lock().unwrap().iter().map(|note| note.uuid))
+            .collect::<Vec::<_>>();
+
         self.insert_notes(notes).iter().for_each(|res| {
             if let Err(e) = res {
                 eprintln!("could not insert new note into table: {e}")
@@ -188,6 +704,14 @@ impl Db {
                 eprintln!("could not remove note: {e}")
             }
         });
+
+        if let Err(e) = tags::remove_unused_tags(&self.0) {
+            eprintln!("could not remove unused tags: {e}")
+        }
+
+        if let Err(e) = links::update(&self.0, notes, &changed) {
+            eprintln!("could not update note links: {e}")
+        }
     }
 }
 
@@ -196,20 +720,31 @@ struct DbThread {
     notes: AtomicNotes,
     stop: Arc::<AtomicBool>,
     removed_notes: AtomicRemovedNotes,
+    trash_retention: Duration,
+    last_purge_time: Duration,
     last_update_time: Option::<Duration>,
-    changed_notes_count: Arc::<AtomicUsize>
+    changed_notes_count: Arc::<AtomicUsize>,
+    changes: Arc::<ChangeLog>
 }
 
 impl DbThread {
     #[inline]
     fn new(
         db: Db,
+        config: &Config,
         stop: Arc::<AtomicBool>,
         notes: AtomicNotes,
         removed_notes: AtomicRemovedNotes,
-        changed_notes_count: Arc::<AtomicUsize>
+        changed_notes_count: Arc::<AtomicUsize>,
+        changes: Arc::<ChangeLog>
     ) -> Self {
-        Self { db, stop, notes, removed_notes, changed_notes_count, last_update_time: None }
+        let now = Self::curr_time();
+        Self {
+            db, stop, notes, removed_notes, changed_notes_count, changes,
+            trash_retention: config.trash_retention,
+            last_purge_time: Duration::ZERO,
+            last_update_time: Some(now)
+        }
     }
 
     #[inline(always)]
@@ -217,6 +752,27 @@ impl DbThread {
         self.db.update(&self.notes, &self.removed_notes)
     }
 
+    /// Permanently removes notes that have been sitting in the trash for longer than the configured retention.
+    fn purge_trash(&mut self) {
+        let now = Self::curr_time();
+        if now - self.last_purge_time < db::TRASH_PURGE_INTERVAL { return }
+        self.last_purge_time = now;
+
+        let deadline = now.saturating_sub(self.trash_retention).as_secs() as UnixTimeStamp;
+        let mut removed_notes = self.removed_notes.lock().unwrap();
+        let count = removed_notes.len();
+        self.notes.retain(|_, note| {
+            let expired = matches!(note.deleted_at, Some(time) if time <= deadline);
+            if expired {
+                let mut note = Arc::clone(note);
+                Arc::make_mut(&mut note).seq = self.changes.tombstone(&note);
+                removed_notes.push(note)
+            }
+            !expired
+        });
+        self.changed_notes_count.fetch_add(removed_notes.len() - count, Ordering::Relaxed);
+    }
+
     #[inline(always)]
     fn curr_time() -> Duration {
         let start = SystemTime::now();
@@ -238,13 +794,17 @@ impl DbThread {
         actix_rt::spawn(async move {
             let mut shutdown = std::pin::pin!(signal::ctrl_c());
             loop {
+                if self.stop.load(Ordering::Relaxed) {
+                    self.update();
+                    break
+                }
                 tokio::select! {
                     _ = &mut shutdown => {
                         self.update();
                         break
                     },
                     _ = async {
-                        if self.stop.load(Ordering::Relaxed) { return }
+                        self.purge_trash();
                         if self.is_update_needed() {
                             self.update();
                             self.changed_notes_count.store(0, Ordering::Relaxed);
@@ -261,74 +821,289 @@ impl DbThread {
 }
 
 struct Server {
+    pool: DbPool,
+    config: Config,
+    workflow: RwLock::<Workflow>,
     notes: AtomicNotes,
-    qr_bytes: web::Bytes,
+    /// Paired devices and pairing tokens handed out, see [`auth`].
+    devices: auth::Devices,
+    /// Who else but their owners gets to see which notes, see [`sharing`].
+    shares: Arc::<sharing::Shares>,
+    /// Fingerprint of the certificate served, when serving HTTPS, see [`tls`].
+    tls_fingerprint: Option::<Box::<str>>,
+    /// The pairing QR code last rendered, along with its pairing token.
+    pairing_qr: Mutex::<Option::<(Box::<str>, web::Bytes)>>,
+    /// How many more changes every client gets to make for now, see [`rate_limit`].
+    rate_limits: rate_limit::Buckets,
     removed_notes: AtomicRemovedNotes,
-    changed_notes_count: Arc::<AtomicUsize>
+    changed_notes_count: Arc::<AtomicUsize>,
+    /// Reminders fired by the [`reminders`] scheduler, streamed to every connected client.
+    reminders: tokio::sync::broadcast::Sender::<Reminder>,
+    /// Every change to the notes, streamed to connected clients so that they stay in sync.
+    events: events::Feed,
+    /// Notes being edited together over WebSockets.
+    collab: collab::Sessions,
+    changes: Arc::<ChangeLog>,
+    /// Flips to `true` once the server starts shutting down, so that long-lived responses can end.
+    shutdown: tokio::sync::watch::Receiver::<bool>
 }
 
 impl Server {
-    #[inline(always)]
-    fn insert_note(&self, note: Note) {
-        _ = self.notes.insert(note.uuid, Arc::new(note))
+    /// The pairing QR code as a PNG, rendered again only once its token is used up or has less than
+    /// [`QR_MIN_TTL`] left, so that there's time to scan it.
+    fn pairing_qr(&self, addr: SocketAddr) -> Result::<web::Bytes, ApiError> {
+        let mut cached = self.pairing_qr.lock().unwrap();
+        if let Some((ref token, ref png)) = *cached {
+            if self.devices.is_pending(token, QR_MIN_TTL) { return Ok(png.clone()) }
+        }
+        let token = self.devices.pairing_token();
+        let url = match &self.tls_fingerprint {
+            Some(fingerprint) => format!("https://{addr}/#pair={token}&cert=sha256:{fingerprint}"),
+            None => format!("http://{addr}/#pair={token}")
+        };
+        let qr = QrCode::encode_text(&url, QrCodeEcc::Low).map_err(|e| ApiError::Internal(format!("could not encode URL to QR code: {e}")))?;
+        let png = web::Bytes::from(gen_qr_png_bytes(&qr).map_err(|_| ApiError::Internal("could not generate QR code image".to_owned()))?);
+        *cached = Some((token, png.clone()));
+        Ok(png)
+    }
+
+    /// Turns away new notes once there are `max_notes` of them, the ones in the trash included.
+    #[inline]
+    fn check_note_quota(&self) -> Result::<(), ApiError> {
+        if self.notes.len() >= self.config.max_notes {
+            return Err(ApiError::QuotaExceeded("the server holds as many notes as it's allowed to"))
+        }
+        Ok(())
+    }
+
+    fn create_note(&self, mut note: Note) -> Arc::<Note> {
+        note.uuid = Uuid::new_v4();
+        self.create_note_with_uuid(note).expect("UUID collision")
+    }
+
+    /// Creates a note under the UUID it already has, like one made by an offline client, unless it's taken.
+    fn create_note_with_uuid(&self, mut note: Note) -> Option::<Arc::<Note>> {
+        note.deleted_at = None;
+        note.items = Vec::new();
+        note.reminded_at = None;
+        note.db_status = NoteDbStatus::New;
+        note.render_description();
+        note.column_position = board::next_column_position(self, &note.status);
+        note.position = ordering::first_position(self);
+        let Entry::Vacant(entry) = self.notes.entry(note.uuid) else { return None };
+        note.seq = self.changes.next_seq();
+        let note = Arc::new(note);
+        entry.insert(Arc::clone(&note));
+        self.changed_notes_count.fetch_add(1, Ordering::Relaxed);
+        self.events.publish(ChangeKind::Created, &note);
+        Some(note)
+    }
+
+    /// Returns a note unless it's in the trash.
+    #[inline]
+    fn get_note(&self, uuid: &Uuid) -> Option::<Arc::<Note>> {
+        self.notes.get(uuid).filter(|e| !e.is_trashed()).map(|e| Arc::clone(e.value()))
+    }
+
+    /// Returns a note, trashed or not, if `user` has at least `access` to it.
+    fn get_any_note_as(&self, user: User, uuid: &Uuid, access: Access) -> Result::<Arc::<Note>, ApiError> {
+        let note = self.notes.get(uuid).map(|e| Arc::clone(e.value())).ok_or(ApiError::NoteNotFound)?;
+        match self.shares.access(user, &note) {
+            Some(granted) if granted >= access => Ok(note),
+            Some(..) => Err(ApiError::Forbidden(access.denied())),
+            // not telling apart notes that don't exist and notes of someone else
+            None => Err(ApiError::NoteNotFound)
+        }
+    }
+
+    /// Returns a note outside of the trash if `user` has at least `access` to it.
+    #[inline]
+    fn get_note_as(&self, user: User, uuid: &Uuid, access: Access) -> Result::<Arc::<Note>, ApiError> {
+        self.get_any_note_as(user, uuid, access).and_then(|note| if note.is_trashed() { Err(ApiError::NoteNotFound) } else { Ok(note) })
+    }
+
+    #[inline]
+    fn can_read(&self, user: User, note: &Note) -> bool {
+        self.shares.access(user, note).is_some()
+    }
+
+    #[inline]
+    fn can_write(&self, user: User, note: &Note) -> bool {
+        self.shares.access(user, note) >= Some(Access::Write)
+    }
+
+    /// Notes outside of the trash that `user` can see, in listing order.
+    #[inline]
+    fn live_notes(&self, user: User) -> Vec::<Arc::<Note>> {
+        let mut notes = self.notes.iter()
+            .filter(|e| !e.is_trashed() && self.can_read(user, e))
+            .map(|e| Arc::clone(e.value()))
+            .collect::<Vec::<_>>();
+        ordering::sort(&mut notes);
+        notes
+    }
+
+    /// Only the owner of a note sees it in the trash.
+    #[inline]
+    fn trashed_notes(&self, user: User) -> Vec::<Arc::<Note>> {
+        self.notes.iter().filter(|e| e.is_trashed() && e.owner == Some(user.0)).map(|e| Arc::clone(e.value())).collect()
+    }
+
+    /// Applies `f` to the cached note and schedules it for the next DB flush. Notes in the trash are left alone.
+    #[inline]
+    fn modify_note(&self, uuid: &Uuid, f: impl FnOnce(&mut Note)) -> Option::<Arc::<Note>> {
+        self.modify_note_if(uuid, |note| !note.is_trashed(), f)
+    }
+
+    fn modify_note_if(
+        &self,
+        uuid: &Uuid,
+        pred: impl FnOnce(&Note) -> bool,
+        f: impl FnOnce(&mut Note)
+    ) -> Option::<Arc::<Note>> {
+        let mut entry = self.notes.get_mut(uuid).filter(|e| pred(e))?;
+        let note = Arc::make_mut(&mut *entry);
+        let title = Box::clone(&note.title);
+        let status = note.status.clone();
+        let trashed = note.is_trashed();
+        let description = note.description.as_ptr();
+        f(note);
+        note.seq = self.changes.next_seq();
+        if note.description.as_ptr() != description {
+            note.render_description()
+        }
+        // a note that hasn't reached the DB yet must still be INSERTed
+        if note.db_status != NoteDbStatus::New {
+            note.db_status = NoteDbStatus::Updated
+        }
+        self.changed_notes_count.fetch_add(1, Ordering::Relaxed);
+        let note = Arc::clone(&entry);
+        // published with the entry still locked, so that changes to one note go out in the order they were made
+        match (trashed, note.is_trashed()) {
+            (false, false) => self.events.publish(ChangeKind::Updated, &note),
+            (false, true) => self.events.publish(ChangeKind::Deleted, &note),
+            (true, false) => self.events.publish(ChangeKind::Created, &note),
+            (true, true) => {}
+        }
+        drop(entry);
+
+        if note.title != title && !note.is_trashed() {
+            links::rename(self, &note, &title)
+        }
+        Some(recurrence::spawn_next(self, &note, &status).unwrap_or(note))
     }
+
+    /// Applies `f` to every note (trashed ones included) matching `pred`, returns how many were modified.
+    fn modify_notes_where(&self, pred: impl Fn(&Note) -> bool, f: impl Fn(&mut Note)) -> usize {
+        let uuids = self.notes.iter().filter(|e| pred(e)).map(|e| *e.key()).collect::<Vec::<_>>();
+        uuids.iter().filter(|uuid| self.modify_note_if(uuid, &pred, &f).is_some()).count()
+    }
+
+    /// Makes sure the note is allowed to move into `status` from the one it's in now.
+    fn check_transition(&self, uuid: &Uuid, status: &Status) -> Result::<(), ApiError> {
+        let Some(note) = self.get_note(uuid) else { return Ok(()) };
+        self.workflow.read().unwrap().check_transition(&note.status, status)
+    }
+
+    #[inline]
+    fn trash_note(&self, uuid: &Uuid) -> Option::<Arc::<Note>> {
+        self.modify_note(uuid, |note| note.deleted_at = Some(unix_now()))
+    }
+
+    #[inline]
+    fn restore_note(&self, uuid: &Uuid) -> Option::<Arc::<Note>> {
+        self.modify_note_if(uuid, Note::is_trashed, |note| note.deleted_at = None)
+    }
+
+    /// Permanently removes a note, only notes in the trash can be removed.
+    fn remove_note(&self, uuid: &Uuid) -> bool {
+        let Some((.., mut note)) = self.notes.remove_if(uuid, |_, note| note.is_trashed()) else { return false };
+        Arc::make_mut(&mut note).seq = self.changes.tombstone(&note);
+        self.removed_notes.lock().unwrap().push(note);
+        self.changed_notes_count.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Marks a response of one of the pre-`/api/v1` routes as deprecated and points to its successor.
+#[inline]
+fn deprecated(mut resp: HttpResponse, successor: &str) -> HttpResponse {
+    let headers = resp.headers_mut();
+    headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{successor}>; rel=\"successor-version\"")) {
+        headers.insert(header::LINK, link);
+    }
+    resp
 }
 
 #[inline]
+/// A QR code for pairing a phone, see [`auth`]. It's the same one until its pairing token gets redeemed or is about
+/// to expire, see [`Server::pairing_qr`].
 #[get("/qr.png")]
-async fn qr_code(state: Data::<Server>) -> impl Responder {
-    HttpResponse::Ok().content_type("image/png").body(web::Bytes::clone(&state.qr_bytes))
+async fn qr_code(state: Data::<Server>, req: HttpRequest) -> ApiResult {
+    if !auth::is_host(&req) { return Err(ApiError::Forbidden(auth::HOST_ONLY)) }
+    let png = state.pairing_qr(req.app_config().local_addr())?;
+    Ok(HttpResponse::Ok().content_type("image/png").insert_header((header::CACHE_CONTROL, "no-store")).body(png))
 }
 
 #[inline]
-#[get("/notes")]
-async fn get_notes(state: Data::<Server>) -> impl Responder {
-    let notes = state.notes.iter().map(|e| Arc::clone(&e.value())).collect::<Vec::<_>>();
-    HttpResponse::Ok().body(serde_json::to_string(&notes).unwrap())
+#[get("/notes", wrap = "middleware::from_fn(auth::authenticate)")]
+async fn get_notes(state: Data::<Server>, user: User) -> impl Responder {
+    deprecated(HttpResponse::Ok().json(state.live_notes(user)), "/api/v1/notes")
 }
 
 #[inline]
-#[post("/new-note")]
-async fn new_note(state: Data::<Server>, note: Json::<Note>) -> impl Responder {
-    let uuid = Uuid::new_v4();
-    state.changed_notes_count.fetch_add(1, Ordering::Relaxed);
-    {
-        let mut note = note.into_inner();
-        note.db_status = NoteDbStatus::New;
-        note.uuid = uuid;
-        state.insert_note(note)
-    }
-    HttpResponse::Ok().json(json!({"uuid": uuid}))
+#[post("/new-note", wrap = "middleware::from_fn(auth::authenticate)")]
+async fn new_note(state: Data::<Server>, user: User, query: web::Query::<TemplateQuery>, note: Json::<Note>) -> ApiResult {
+    let mut note = note.into_inner();
+    state.check_note_quota()?;
+    note.owner = Some(user.0);
+    templates::apply(&state, user, &query, &mut note, |note| {
+        note.validate(&state.config)?;
+        notebooks::check_notebook(&state, note.owner, note.notebook.as_ref())?;
+        state.workflow.read().unwrap().check_new(&mut note.status)
+    })?;
+    let note = state.create_note(note);
+    Ok(deprecated(HttpResponse::Ok().json(json!({"uuid": note.uuid})), "/api/v1/notes"))
 }
 
-#[put("/update-note")]
-async fn update_note(state: Data::<Server>, json: Json::<json::Note>) -> impl Responder {
-    let note = json.into_inner();
-    if let Some(mut old_note) = state.notes.get_mut(&note.uuid) {
-        let old_note = Arc::make_mut(&mut *old_note);
-        old_note.uuid = note.uuid;
+#[put("/update-note", wrap = "middleware::from_fn(auth::authenticate)")]
+async fn update_note(state: Data::<Server>, user: User, json: Json::<json::Note>) -> ApiResult {
+    let mut note = json.into_inner();
+    note.validate(&state.config)?;
+    let writable = match state.get_note_as(user, &note.uuid, Access::Write) {
+        Ok(..) => true,
+        Err(ApiError::NoteNotFound) => false,
+        Err(e) => return Err(e)
+    };
+    state.check_transition(&note.uuid, &note.status)?;
+    let resp = if writable && state.modify_note(&note.uuid, |old_note| {
         old_note.title = note.title;
         old_note.status = note.status;
         old_note.mod_time = note.mod_time;
-        old_note.db_status = NoteDbStatus::Updated;
         old_note.description = note.description;
-        state.changed_notes_count.fetch_add(1, Ordering::Relaxed);
+    }).is_some() {
         HttpResponse::Ok().json(json!({"status": "note updated successfully"}))
     } else {
         HttpResponse::NotFound().json(json!({"status": "note not found"}))
-    }
+    };
+    Ok(deprecated(resp, &format!("/api/v1/notes/{uuid}", uuid = note.uuid)))
 }
 
-#[delete("/remove-note")]
-async fn remove_note(state: Data::<Server>, json: Json::<json::Uuid>) -> impl Responder {
+#[delete("/remove-note", wrap = "middleware::from_fn(auth::authenticate)")]
+async fn remove_note(state: Data::<Server>, user: User, json: Json::<json::Uuid>) -> ApiResult {
     let uuid = json.into_inner().uuid;
-    if let Some((.., note)) = state.notes.remove(&uuid) {
-        state.removed_notes.lock().unwrap().push(note);
-        state.changed_notes_count.fetch_add(1, Ordering::Relaxed);
-        HttpResponse::Ok().json(json!({"status": "note removed successfully"}))
+    let removable = match state.get_note_as(user, &uuid, Access::Owner) {
+        Ok(..) => true,
+        Err(ApiError::NoteNotFound) => false,
+        Err(e) => return Err(e)
+    };
+    let resp = if removable && state.trash_note(&uuid).is_some() {
+        HttpResponse::Ok().json(json!({"status": "note moved to trash"}))
     } else {
         HttpResponse::NotFound().json(json!({"status": "note not found"}))
-    }
+    };
+    Ok(deprecated(resp, &format!("/api/v1/notes/{uuid}")))
 }
 
 #[inline]
@@ -338,53 +1113,109 @@ fn get_default_local_ip_addr() -> Option::<IpAddr> {
     sock.local_addr().ok().map(|addr| addr.ip())
 }
 
+/// Resolves on SIGINT or SIGTERM, standing in for the signal handling of actix so that `shutdown` gets notified first.
+async fn shutdown_signal(shutdown: tokio::sync::watch::Sender::<bool>) {
+    #[cfg(unix)] {
+        use signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+        tokio::select! {
+            _ = signal::ctrl_c() => {},
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))] {
+        _ = signal::ctrl_c().await;
+    }
+    shutdown.send_replace(true);
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result::<()> {
     let local_ip = get_default_local_ip_addr().unwrap_or_else(|| panic!("could not find local IP address"));
+    let config = Config::from_env();
+    let tls = config.tls.then(|| tls::load(&config, local_ip)).transpose()?;
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
     let db = Db::new();
+    let pool = Db::pool();
     let notes = Arc::new(db.get_notes().unwrap());
+    links::rebuild(&db.0, &notes).unwrap();
+    let workflow = Workflow::load(&db.0).unwrap();
+    workflow.unknown_statuses(notes.iter().map(|e| e.status.clone())).iter().for_each(|(status, count)| {
+        eprintln!("[WARN] {count} note(s) have status \"{status}\", which is not defined in the workflow")
+    });
     let removed_notes = Arc::new(Mutex::new(Vec::new()));
     let db_thread_stop = Arc::new(AtomicBool::new(false));
     let changed_notes_count = Arc::new(AtomicUsize::new(0));
+    let devices = auth::Devices::load(&db.0).unwrap();
+    let shares = Arc::new(sharing::Shares::load(&db.0).unwrap());
+    let changes = Arc::new(ChangeLog::load(&db.0, &notes, Arc::clone(&shares)).unwrap());
 
     let db_thread = DbThread::new(
         db,
+        &config,
         Arc::clone(&db_thread_stop),
         Arc::clone(&notes),
         Arc::clone(&removed_notes),
-        Arc::clone(&changed_notes_count)
+        Arc::clone(&changed_notes_count),
+        Arc::clone(&changes)
     );
 
     let db_thread_handle = db_thread.spawn();
 
+    let json_config = api::json_config(&config);
+    let (shutdown_tx, shutdown) = tokio::sync::watch::channel(false);
     let server = Data::new(Server {
-        notes, removed_notes, changed_notes_count,
-        qr_bytes: {
-            let local_addr = format!("http://{local_ip}:{PORT}");
-            let qr = QrCode::encode_text(&local_addr, QrCodeEcc::Low).expect("could not encode URL to QR code");
-            gen_qr_png_bytes(&qr).expect("could not generate QR code image").into()
-        }
+        pool, config, notes, removed_notes, changed_notes_count,
+        shutdown, changes,
+        workflow: RwLock::new(workflow),
+        reminders: tokio::sync::broadcast::channel(reminders::CHANNEL_CAPACITY).0,
+        events: events::Feed::new(),
+        collab: collab::Sessions::default(),
+        devices, shares,
+        tls_fingerprint: tls.as_ref().map(|tls| tls.fingerprint.clone()),
+        pairing_qr: Mutex::default(),
+        rate_limits: rate_limit::Buckets::default()
     });
+    if let Err(e) = server.pairing_qr(SocketAddr::new(local_ip, PORT)) {
+        eprintln!("[WARN] could not render the pairing QR code: {e}")
+    }
+
+    let reminders_handle = reminders::spawn(Data::clone(&server), Arc::clone(&db_thread_stop));
 
-    println!("[INFO] serving at: <http://{local_ip}:{PORT}>");
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    println!("[INFO] serving at: <{scheme}://{local_ip}:{PORT}>");
+    if let Some(tls) = &tls {
+        println!("[INFO] certificate fingerprint: sha256:{fingerprint}", fingerprint = tls.fingerprint);
+    }
 
-    HttpServer::new(move || {
+    let http_server = HttpServer::new(move || {
         App::new()
+            .wrap(middleware::from_fn(rate_limit::limit))
             .wrap(Logger::default())
             .app_data(Data::clone(&server))
+            .app_data(json_config.clone())
+            .app_data(api::path_config())
+            .app_data(api::query_config())
+
+            .service(web::scope("/api/v1").configure(api::config))
 
             .service(qr_code)
             .service(new_note)
             .service(get_notes)
             .service(remove_note)
             .service(update_note)
+            .service(share_links::view)
             .service(Files::new("/", "static").index_file("index.html"))
-    }).bind((local_ip, PORT))?.run().await?;
+    }).shutdown_signal(shutdown_signal(shutdown_tx));
+    match tls {
+        Some(tls) => http_server.bind_rustls_0_23((local_ip, PORT), tls.config)?.run().await?,
+        None => http_server.bind((local_ip, PORT))?.run().await?
+    }
 
     db_thread_stop.store(true, Ordering::Relaxed);
     db_thread_handle.await.unwrap();
+    reminders_handle.await.unwrap();
 
     Ok(())
 }
@@ -0,0 +1,28 @@
+//! Note descriptions are CommonMark (with tables, task lists and strikethrough), rendered to HTML
+//! that is safe to put into the page as is, raw HTML in the source is sanitized away.
+
+use std::sync::LazyLock;
+
+use pulldown_cmark::{html, Options, Parser};
+
+static SANITIZER: LazyLock::<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut builder = ammonia::Builder::default();
+    // task list checkboxes, they're rendered disabled
+    builder
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("td", ["style"])
+        .add_tag_attributes("th", ["style"])
+        .filter_style_properties(["text-align"].into());
+    builder
+});
+
+const OPTIONS: Options = Options::ENABLE_TABLES
+    .union(Options::ENABLE_TASKLISTS)
+    .union(Options::ENABLE_STRIKETHROUGH);
+
+pub fn render(src: &str) -> Box::<str> {
+    let mut out = String::with_capacity(src.len() * 3 / 2);
+    html::push_html(&mut out, Parser::new_ext(src, OPTIONS));
+    SANITIZER.clean(&out).to_string().into()
+}
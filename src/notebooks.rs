@@ -0,0 +1,112 @@
+//! Notebooks are a single level of folders, a note belongs to at most one of them. Every user has notebooks of
+//! their own, and sees the ones shared with them too, see [`crate::sharing`].
+
+use crate::*;
+
+#[derive(Serialize)]
+struct Notebook {
+    uuid: Uuid,
+    name: Box::<str>,
+    owner: Uuid,
+    /// Notes in it that the user can see.
+    count: usize
+}
+
+/// Makes sure the notebook a note of `owner` is about to be put into exists and is theirs.
+pub fn check_notebook(state: &Server, owner: Option::<Uuid>, notebook: Option::<&Uuid>) -> Result::<(), ApiError> {
+    let Some(uuid) = notebook else { return Ok(()) };
+    match state.pool.get()?.query_row(
+        "SELECT 1 FROM notebooks WHERE uuid = ?1 AND owner IS ?2",
+        params![uuid.to_string(), owner.map(|u| u.to_string())],
+        |_| Ok(())
+    ) {
+        Ok(_) => Ok(()),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(ApiError::Validation(vec![validate::FieldError {
+            field: "notebook",
+            message: format!("notebook {uuid} does not exist")
+        }])),
+        Err(e) => Err(e.into())
+    }
+}
+
+/// Only the owner of a note moves it into another notebook, since that changes who it's shared with.
+pub fn check_move(state: &Server, user: User, note: &Note, notebook: Option::<&Uuid>) -> Result::<(), ApiError> {
+    if note.notebook.as_ref() == notebook { return Ok(()) }
+    if note.owner != Some(user.0) {
+        return Err(ApiError::Forbidden("only the owner of a note can move it to another notebook"))
+    }
+    check_notebook(state, note.owner, notebook)
+}
+
+#[get("/notebooks")]
+async fn list_notebooks(state: Data::<Server>, user: User) -> ApiResult {
+    let notes = state.live_notes(user);
+    let conn = state.pool.get()?;
+    let mut stmt = conn.prepare("SELECT uuid, name, owner FROM notebooks WHERE owner IS NOT NULL ORDER BY name")?;
+    let notebooks = stmt.query_map([], |row| {
+        let uuid = Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID");
+        Ok(Notebook {
+            uuid,
+            name: row.get(1)?,
+            owner: Uuid::parse_str(&row.get::<_, String>(2)?).expect("invalid UUID"),
+            count: notes.iter().filter(|note| note.notebook == Some(uuid)).count()
+        })
+    })?.filter(|notebook| notebook.as_ref().map_or(true, |notebook| {
+        notebook.owner == user.0 || state.shares.notebook_access(user, &notebook.uuid).is_some()
+    })).collect::<Result::<Vec::<_>>>()?;
+    Ok(HttpResponse::Ok().json(notebooks))
+}
+
+#[post("/notebooks")]
+async fn create_notebook(state: Data::<Server>, user: User, json: Json::<json::Name>) -> ApiResult {
+    let mut name = json.into_inner().name;
+    validate::notebook_name(&mut name, &state.config)?;
+    let uuid = Uuid::new_v4();
+    state.pool.get()?.execute(
+        "INSERT INTO notebooks (uuid, name, owner) VALUES (?1, ?2, ?3)",
+        params![uuid.to_string(), name, user.0.to_string()]
+    ).map_err(|e| ApiError::from_unique(e, "notebook", &name))?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/api/v1/notebooks/{uuid}")))
+        .json(Notebook { uuid, name, owner: user.0, count: 0 }))
+}
+
+#[patch("/notebooks/{uuid}")]
+async fn rename_notebook(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, json: Json::<json::Name>) -> ApiResult {
+    let mut name = json.into_inner().name;
+    validate::notebook_name(&mut name, &state.config)?;
+    let updated = state.pool.get()?.execute(
+        "UPDATE notebooks SET name = ?1 WHERE uuid = ?2 AND owner = ?3",
+        params![name, uuid.to_string(), user.0.to_string()]
+    ).map_err(|e| ApiError::from_unique(e, "notebook", &name))?;
+    if updated == 0 { return Err(ApiError::NotFound) }
+    let count = state.live_notes(user).iter().filter(|note| note.notebook == Some(*uuid)).count();
+    Ok(HttpResponse::Ok().json(Notebook { uuid: *uuid, name, owner: user.0, count }))
+}
+
+/// Removes the notebook, its notes are kept and just taken out of it.
+#[delete("/notebooks/{uuid}")]
+async fn delete_notebook(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    let removed = state.pool.get()?.execute(
+        "DELETE FROM notebooks WHERE uuid = ?1 AND owner = ?2",
+        params![uuid.to_string(), user.0.to_string()]
+    )?;
+    if removed == 0 { return Err(ApiError::NotFound) }
+    let seen = state.shares.notebook_readers(&uuid).into_iter().map(|with| {
+        (User(with), state.live_notes(User(with)).into_iter().filter(|note| note.notebook == Some(*uuid)).collect())
+    }).collect::<Vec::<_>>();
+    state.shares.remove_notebook(&uuid);
+    state.modify_notes_where(|note| note.notebook == Some(*uuid), |note| note.notebook = None);
+    for (with, seen) in seen {
+        sharing::revoke(&state, with, seen)?
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_notebooks)
+        .service(create_notebook)
+        .service(rename_notebook)
+        .service(delete_notebook);
+}
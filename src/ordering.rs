@@ -0,0 +1,90 @@
+//! Notes are listed pinned ones first, then by [`Note::position`]. Positions are fractional so moving
+//! a note only ever rewrites that note, unless its neighbours got so close that there's no room left
+//! between them, then the whole group gets renumbered.
+
+use crate::*;
+
+#[derive(Deserialize)]
+struct Reorder {
+    /// Index within the pinned or the unpinned notes to move the note to, the end if left out.
+    index: Option::<usize>,
+    /// Pins or unpins the note as part of the move.
+    pinned: Option::<bool>
+}
+
+#[inline]
+pub fn sort(notes: &mut [Arc::<Note>]) {
+    notes.sort_by(|a, b| {
+        b.pinned.cmp(&a.pinned)
+            .then_with(|| a.position.total_cmp(&b.position))
+            .then_with(|| a.uuid.cmp(&b.uuid))
+    })
+}
+
+/// Position that puts a note before every other note.
+#[inline]
+pub fn first_position(state: &Server) -> f64 {
+    state.notes.iter().map(|e| e.position).min_by(f64::total_cmp).map_or(0.0, |position| position - 1.0)
+}
+
+/// Position that puts a note at `index` of `notes` (already sorted by `position`),
+/// `None` if there's no room left between the neighbours.
+pub fn position_at(notes: &[Arc::<Note>], index: usize, position: impl Fn(&Note) -> f64) -> Option::<f64> {
+    let before = index.checked_sub(1).and_then(|i| notes.get(i)).map(|note| position(note));
+    let after = notes.get(index).map(|note| position(note));
+    match (before, after) {
+        (None, None) => Some(0.0),
+        (Some(before), None) => Some(before + 1.0),
+        (None, Some(after)) => Some(after - 1.0),
+        (Some(before), Some(after)) => {
+            let mid = before + (after - before) / 2.0;
+            (before < mid && mid < after).then_some(mid)
+        }
+    }
+}
+
+/// Makes room at `index` of `group` by renumbering the notes in it, only the ones `user` gets to change though.
+/// The others are left out of `group`, along with the room between them, returns the position at `index`.
+pub fn renumber(
+    state: &Server,
+    user: User,
+    group: &mut Vec::<Arc::<Note>>,
+    index: usize,
+    position: impl Fn(&Note) -> f64,
+    set_position: impl Fn(&mut Note, f64)
+) -> f64 {
+    let index = group[..index].iter().filter(|note| state.can_write(user, note)).count();
+    group.retain(|note| state.can_write(user, note));
+    for (i, note) in group.iter_mut().enumerate() {
+        if let Some(renumbered) = state.modify_note(&note.uuid, |note| set_position(note, i as f64)) {
+            *note = renumbered
+        }
+    }
+    position_at(group, index, position).expect("renumbered notes always have room")
+}
+
+#[post("/notes/{uuid}/reorder")]
+async fn reorder_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, json: Json::<Reorder>) -> ApiResult {
+    let Reorder { index, pinned } = json.into_inner();
+    let note = state.get_note_as(user, &uuid, Access::Write)?;
+    let pinned = pinned.unwrap_or(note.pinned);
+
+    // the index is into the notes as the user sees them
+    let mut group = state.live_notes(user);
+    group.retain(|note| note.pinned == pinned && note.uuid != *uuid);
+    let index = index.unwrap_or(group.len()).min(group.len());
+    let position = match position_at(&group, index, |note| note.position) {
+        Some(position) => position,
+        None => renumber(&state, user, &mut group, index, |note| note.position, |note, position| note.position = position)
+    };
+
+    let note = state.modify_note(&uuid, |note| {
+        note.pinned = pinned;
+        note.position = position;
+    }).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(reorder_note);
+}
@@ -1,6 +1,6 @@
 use qrcodegen::QrCode;
 
-use crate::stb_image_write::*;
+use crate::image_write::write_png_to_memory;
 
 const SCALE: usize = 10;
 const BORDER: usize = 2;
@@ -23,24 +23,5 @@ pub fn gen_qr_png_bytes(qr: &QrCode) -> Result::<Vec::<u8>, ()> {
         }
     }
 
-    unsafe { write_png_to_memory(&image, img_size as _, img_size as _) }
-}
-
-pub unsafe fn write_png_to_memory(image: &[u8], width: i32, height: i32) -> Result::<Vec::<u8>, ()> {
-    let mut out_len = 0;
-
-    let ret = stbi_write_png_to_mem(
-        image.as_ptr(),
-        width,
-        width,
-        height,
-        1,
-        &mut out_len as *mut i32
-    );
-
-    if !ret.is_null() {
-        Ok(std::slice::from_raw_parts_mut(ret, out_len as usize).to_vec())
-    } else {
-        Err(())
-    }
+    write_png_to_memory(&image, img_size, img_size)
 }
@@ -0,0 +1,83 @@
+//! Limits how fast any one client gets to change things, so that a single device on the network can't flood the
+//! server. Every client has a token bucket holding up to `rate_limit_burst` requests, refilled at
+//! `rate_limit_per_minute`, and every request other than `GET`, `HEAD` and `OPTIONS` takes one out of it. Once it's
+//! empty, requests get a 429 with `Retry-After` until it refills.
+//!
+//! Paired devices are told apart by their token, anything else by its IP, so that made up tokens don't get a bucket
+//! of their own. Operations sent over a collab socket come out of the bucket of the device too, see [`Buckets::take_op`].
+
+use crate::*;
+
+use std::time::Instant;
+
+use actix_web::{
+    Error,
+    http::Method,
+    body::MessageBody,
+    middleware::Next,
+    dev::{ServiceRequest, ServiceResponse}
+};
+
+/// Buckets kept around before the full ones get dropped, they're as good as new anyway.
+const PRUNE_THRESHOLD: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Client {
+    Device(Uuid),
+    Ip(IpAddr)
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant
+}
+
+#[derive(Default)]
+pub struct Buckets(DashMap::<Client, Bucket>);
+
+impl Buckets {
+    /// Takes a request out of the bucket of `client`, returns how many seconds until there's one if it's empty.
+    fn take(&self, client: Client, config: &Config) -> Result::<(), u64> {
+        let burst = f64::from(config.rate_limit_burst);
+        let per_sec = f64::from(config.rate_limit_per_minute) / 60.0;
+        let now = Instant::now();
+        if self.0.len() > PRUNE_THRESHOLD {
+            self.0.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * per_sec < burst)
+        }
+
+        let mut bucket = self.0.entry(client).or_insert(Bucket { tokens: burst, refilled_at: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.refilled_at).as_secs_f64() * per_sec).min(burst);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_sec).ceil() as _)
+        }
+    }
+
+    /// Takes a change made over a socket rather than with a request of its own out of the bucket of the device.
+    pub fn take_op(&self, session: &auth::Session, config: &Config) -> Result::<(), u64> {
+        if config.rate_limit_per_minute == 0 { return Ok(()) }
+        self.take(Client::Device(session.device), config)
+    }
+}
+
+/// Middleware applying the limit, turned off by setting `rate_limit_per_minute` to 0.
+pub async fn limit(req: ServiceRequest, next: Next::<impl MessageBody>) -> Result::<ServiceResponse::<impl MessageBody>, Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) { return next.call(req).await }
+    let state = req.app_data::<Data::<Server>>().expect("missing server state");
+    if state.config.rate_limit_per_minute == 0 { return next.call(req).await }
+
+    let client = match auth::device(state, req.request()) {
+        Some(device) => Client::Device(device),
+        None => match req.peer_addr() {
+            Some(peer) => Client::Ip(peer.ip()),
+            None => return next.call(req).await
+        }
+    };
+    if let Err(retry_after) = state.rate_limits.take(client, &state.config) {
+        return Err(ApiError::TooManyRequests(retry_after).into())
+    }
+    next.call(req).await
+}
@@ -0,0 +1,402 @@
+//! Repeating notes. A note with a [`Recurrence`] that moves into a done status spawns its next occurrence,
+//! a copy of it due one period later, and stops recurring itself.
+//!
+//! Rules are a subset of RFC 5545 RRULEs: `FREQ` (`DAILY`, `WEEKLY`, `MONTHLY` or `YEARLY`), `INTERVAL`, `COUNT`,
+//! `UNTIL`, `BYDAY` (weekly rules only, without ordinals) and `BYMONTHDAY` (a single positive day), or just one of
+//! `daily`, `weekly`, `monthly` and `yearly`. Dates are computed in UTC, an occurrence that would fall past
+//! the end of a shorter month falls on its last day instead.
+
+use crate::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+pub const SECS_PER_DAY: i64 = 24 * 60 * 60;
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recurrence {
+    freq: Freq,
+    interval: u32,
+    /// Occurrences left, the current one included.
+    count: Option::<u32>,
+    until: Option::<UnixTimeStamp>,
+    /// Weekdays from 0 for Monday to 6 for Sunday, sorted.
+    by_day: Vec::<u8>,
+    by_month_day: Option::<u32>
+}
+
+/// Days since 1970-01-01 to a `(year, month, day)` date, see <https://howardhinnant.github.io/date_algorithms.html>.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[inline]
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+/// 0 for Monday, 1970-01-01 was a Thursday.
+#[inline(always)]
+fn weekday(days: i64) -> u8 {
+    (days + 3).rem_euclid(7) as u8
+}
+
+/// Accepts `YYYYMMDD`, which lasts until the end of that day, and `YYYYMMDDTHHMMSS` with an optional `Z`.
+fn parse_until(s: &str) -> Option::<UnixTimeStamp> {
+    let s = s.strip_suffix('Z').unwrap_or(s);
+    let (date, time) = s.split_once('T').unwrap_or((s, ""));
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) { return None }
+    let (year, month, day) = (date[..4].parse().ok()?, date[4..6].parse().ok()?, date[6..].parse().ok()?);
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) { return None }
+    let secs = match time {
+        "" => SECS_PER_DAY - 1,
+        time if time.len() == 6 && time.bytes().all(|b| b.is_ascii_digit()) => {
+            let (h, m, s) = (time[..2].parse::<i64>().ok()?, time[2..4].parse::<i64>().ok()?, time[4..].parse::<i64>().ok()?);
+            if h > 23 || m > 59 || s > 59 { return None }
+            h * 3600 + m * 60 + s
+        }
+        _ => return None
+    };
+    Some(days_from_civil(year, month, day) * SECS_PER_DAY + secs)
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result::<Self, Self::Err> {
+        let s = s.trim();
+        let mut rule = Self { freq: Freq::Daily, interval: 1, count: None, until: None, by_day: Vec::new(), by_month_day: None };
+        let shorthand = match &*s.to_ascii_lowercase() {
+            "daily" => Some(Freq::Daily),
+            "weekly" => Some(Freq::Weekly),
+            "monthly" => Some(Freq::Monthly),
+            "yearly" => Some(Freq::Yearly),
+            _ => None
+        };
+        if let Some(freq) = shorthand { return Ok(Self { freq, ..rule }) }
+
+        let mut freq = None;
+        for part in s.strip_prefix("RRULE:").unwrap_or(s).split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got {part:?}"))?;
+            let invalid = || format!("invalid {key}: {value:?}");
+            match &*key.to_ascii_uppercase() {
+                "FREQ" => freq = Some(match &*value.to_ascii_uppercase() {
+                    "DAILY" => Freq::Daily,
+                    "WEEKLY" => Freq::Weekly,
+                    "MONTHLY" => Freq::Monthly,
+                    "YEARLY" => Freq::Yearly,
+                    _ => return Err(format!("unsupported FREQ: {value:?}"))
+                }),
+                "INTERVAL" => rule.interval = value.parse().ok().filter(|&interval| interval > 0).ok_or_else(invalid)?,
+                "COUNT" => rule.count = Some(value.parse().ok().filter(|&count| count > 0).ok_or_else(invalid)?),
+                "UNTIL" => rule.until = Some(parse_until(value).ok_or_else(invalid)?),
+                "BYDAY" => {
+                    rule.by_day = value.split(',').map(|day| {
+                        WEEKDAYS.iter().position(|d| d.eq_ignore_ascii_case(day.trim())).map(|d| d as u8)
+                    }).collect::<Option::<Vec::<_>>>().ok_or_else(invalid)?;
+                    rule.by_day.sort_unstable();
+                    rule.by_day.dedup()
+                }
+                "BYMONTHDAY" => rule.by_month_day = Some(value.parse().ok().filter(|day| (1..=31).contains(day)).ok_or_else(invalid)?),
+                _ => return Err(format!("unsupported rule part: {key}"))
+            }
+        }
+        rule.freq = freq.ok_or("FREQ is required")?;
+        if rule.count.is_some() && rule.until.is_some() { return Err("COUNT and UNTIL can't be used together".to_owned()) }
+        if !rule.by_day.is_empty() && rule.freq != Freq::Weekly { return Err("BYDAY is only supported with FREQ=WEEKLY".to_owned()) }
+        if rule.by_month_day.is_some() && !matches!(rule.freq, Freq::Monthly | Freq::Yearly) {
+            return Err("BYMONTHDAY is only supported with FREQ=MONTHLY or FREQ=YEARLY".to_owned())
+        }
+        Ok(rule)
+    }
+}
+
+impl TryFrom::<String> for Recurrence {
+    type Error = String;
+
+    #[inline(always)]
+    fn try_from(s: String) -> Result::<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let freq = match self.freq {
+            Freq::Daily => "DAILY",
+            Freq::Weekly => "WEEKLY",
+            Freq::Monthly => "MONTHLY",
+            Freq::Yearly => "YEARLY"
+        };
+        write!(f, "FREQ={freq}")?;
+        if self.interval != 1 { write!(f, ";INTERVAL={interval}", interval = self.interval)? }
+        if let Some(count) = self.count { write!(f, ";COUNT={count}")? }
+        if let Some(until) = self.until {
+            let (year, month, day) = civil_from_days(until.div_euclid(SECS_PER_DAY));
+            let secs = until.rem_euclid(SECS_PER_DAY);
+            write!(f, ";UNTIL={year:04}{month:02}{day:02}T{h:02}{m:02}{s:02}Z", h = secs / 3600, m = secs / 60 % 60, s = secs % 60)?
+        }
+        if !self.by_day.is_empty() {
+            let days = self.by_day.iter().map(|&day| WEEKDAYS[day as usize]).collect::<Vec::<_>>();
+            write!(f, ";BYDAY={days}", days = days.join(","))?
+        }
+        if let Some(day) = self.by_month_day { write!(f, ";BYMONTHDAY={day}")? }
+        Ok(())
+    }
+}
+
+impl From::<Recurrence> for String {
+    #[inline(always)]
+    fn from(rule: Recurrence) -> Self {
+        rule.to_string()
+    }
+}
+
+impl Recurrence {
+    fn add_months(&self, days: i64, months: i64) -> Option::<i64> {
+        let (year, month, day) = civil_from_days(days);
+        let months = (year * 12 + month as i64 - 1).checked_add(months)?;
+        let (year, month) = (months.div_euclid(12), months.rem_euclid(12) as u32 + 1);
+        // way past any timestamp, and past what the date math is good for
+        if year.unsigned_abs() > (i64::MAX / SECS_PER_DAY / 366) as u64 { return None }
+        let day = self.by_month_day.unwrap_or(day).min(days_in_month(year, month));
+        Some(days_from_civil(year, month, day))
+    }
+
+    /// Moves `days` on by whole periods of the rule, `None` if that's out of reach.
+    fn advance(&self, days: i64, periods: i64) -> Option::<i64> {
+        let interval = self.interval as i64;
+        match self.freq {
+            Freq::Daily => days.checked_add(periods.checked_mul(interval)?),
+            Freq::Weekly => days.checked_add(periods.checked_mul(7 * interval)?),
+            Freq::Monthly => self.add_months(days, periods.checked_mul(interval)?),
+            Freq::Yearly => self.add_months(days, periods.checked_mul(12 * interval)?)
+        }
+    }
+
+    /// How many whole periods of the rule there are at least from the day `from` to the day `to`.
+    fn periods_between(&self, from: i64, to: i64) -> i64 {
+        let interval = self.interval as i64;
+        let periods = match self.freq {
+            Freq::Daily => (to - from) / interval,
+            Freq::Weekly => (to - from) / (7 * interval),
+            Freq::Monthly | Freq::Yearly => {
+                let ((from_year, from_month, _), (to_year, to_month, _)) = (civil_from_days(from), civil_from_days(to));
+                // one month short, as the day of the month may not have come yet
+                let months = (to_year - from_year) * 12 + to_month as i64 - from_month as i64 - 1;
+                months / if self.freq == Freq::Monthly { interval } else { 12 * interval }
+            }
+        };
+        periods.max(0)
+    }
+
+    /// The occurrence right after the one at `time`, keeping its time of day.
+    fn step(&self, time: UnixTimeStamp) -> Option::<UnixTimeStamp> {
+        let (days, secs) = (time.div_euclid(SECS_PER_DAY), time.rem_euclid(SECS_PER_DAY));
+        let days = match self.freq {
+            Freq::Weekly if !self.by_day.is_empty() => {
+                let today = weekday(days);
+                match self.by_day.iter().find(|&&day| day > today) {
+                    Some(&day) => days + (day - today) as i64,
+                    None => self.advance(days - today as i64 + self.by_day[0] as i64, 1)?
+                }
+            }
+            _ => self.advance(days, 1)?
+        };
+        days.checked_mul(SECS_PER_DAY)?.checked_add(secs)
+    }
+
+    /// The rule and the due date of the occurrence after the one due at `due_at`, `None` once the rule has run out,
+    /// or the next occurrence is out of reach. Occurrences that would already be overdue by `now` are skipped.
+    pub fn next(&self, due_at: UnixTimeStamp, now: UnixTimeStamp) -> Option::<(Self, UnixTimeStamp)> {
+        let mut rule = self.clone();
+        // pin the day of the month so that a short month doesn't move every later occurrence
+        if matches!(rule.freq, Freq::Monthly | Freq::Yearly) && rule.by_month_day.is_none() {
+            rule.by_month_day = Some(civil_from_days(due_at.div_euclid(SECS_PER_DAY)).2)
+        }
+        let mut next = rule.step(due_at)?;
+        if next <= now {
+            // skip the whole periods in between at once, `due_at` may be ages ago
+            let (days, secs) = (next.div_euclid(SECS_PER_DAY), next.rem_euclid(SECS_PER_DAY));
+            let days = rule.advance(days, rule.periods_between(days, now.div_euclid(SECS_PER_DAY)))?;
+            next = days.checked_mul(SECS_PER_DAY)?.checked_add(secs)?;
+            while next <= now { next = rule.step(next)? }
+        }
+        if let Some(count) = rule.count {
+            if count <= 1 { return None }
+            rule.count = Some(count - 1)
+        }
+        if rule.until.is_some_and(|until| next > until) { return None }
+        Some((rule, next))
+    }
+}
+
+/// Called after every modification of a note, spawns the next occurrence of `note` if it has just been completed.
+/// Returns the completed note, which doesn't recur anymore, if it did.
+pub fn spawn_next(state: &Server, note: &Note, old_status: &Status) -> Option::<Arc::<Note>> {
+    let rule = note.recurrence.as_ref()?;
+    if note.is_trashed() || note.status == *old_status { return None }
+    let status = {
+        let workflow = state.workflow.read().unwrap();
+        // a renamed or removed status moves notes too, that's not completing them
+        if !workflow.is_done(&note.status) || workflow.get(old_status).is_none_or(|def| def.done) { return None }
+        workflow.statuses.iter().find(|def| !def.done)?.name.clone()
+    };
+
+    let completed = state.modify_note(&note.uuid, |note| note.recurrence = None);
+    let now = unix_now();
+    let due_at = note.due_at.unwrap_or(now);
+    let Some((recurrence, next_due_at)) = rule.next(due_at, now) else { return completed };
+    let remind_at = match note.remind_at {
+        Some(remind_at) => match next_due_at.checked_sub(due_at).and_then(|offset| remind_at.checked_add(offset)) {
+            Some(remind_at) => Some(remind_at),
+            // as good as out of reach
+            None => return completed
+        },
+        None => None
+    };
+    let next = state.create_note(Note {
+        status,
+        mod_time: now,
+        due_at: Some(next_due_at),
+        remind_at,
+        recurrence: Some(recurrence),
+        ..Note::clone(note)
+    });
+    if !note.items.is_empty() {
+        let items = note.items.iter().map(ChecklistItem::unchecked).collect::<Vec::<_>>();
+        state.modify_note(&next.uuid, |next| next.items = items);
+    }
+    completed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i64, month: u32, day: u32, secs: i64) -> UnixTimeStamp {
+        days_from_civil(year, month, day) * SECS_PER_DAY + secs
+    }
+
+    fn rule(s: &str) -> Recurrence {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        for days in [-719468, -1, 0, 11016, 19782, 2932896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days)
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(rule("Weekly"), Recurrence { freq: Freq::Weekly, interval: 1, count: None, until: None, by_day: vec![], by_month_day: None });
+        let parsed = rule("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=fr,MO,FR;COUNT=3");
+        assert_eq!(parsed.by_day, [0, 4]);
+        assert_eq!(parsed.to_string(), "FREQ=WEEKLY;INTERVAL=2;COUNT=3;BYDAY=MO,FR");
+        assert_eq!(rule("FREQ=DAILY;UNTIL=20250102").until, Some(at(2025, 1, 2, SECS_PER_DAY - 1)));
+        assert_eq!(rule("FREQ=DAILY;UNTIL=20250102T103000Z").until, Some(at(2025, 1, 2, 10 * 3600 + 30 * 60)));
+        assert_eq!(rule(&rule("FREQ=MONTHLY;BYMONTHDAY=31;UNTIL=20250102T103000Z").to_string()), rule("FREQ=MONTHLY;BYMONTHDAY=31;UNTIL=20250102T103000"));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for s in [
+            "", "INTERVAL=2", "FREQ=HOURLY", "FREQ=DAILY;INTERVAL=0", "FREQ=DAILY;COUNT=0", "FREQ=DAILY;COUNT=2;UNTIL=20250101",
+            "FREQ=DAILY;UNTIL=20250230", "FREQ=DAILY;UNTIL=20250101T240000", "FREQ=MONTHLY;BYDAY=MO", "FREQ=WEEKLY;BYDAY=XX",
+            "FREQ=WEEKLY;BYMONTHDAY=1", "FREQ=MONTHLY;BYMONTHDAY=32", "FREQ=DAILY;BYSETPOS=1", "FREQ"
+        ] {
+            assert!(s.parse::<Recurrence>().is_err(), "{s:?}")
+        }
+    }
+
+    #[test]
+    fn monthly_keeps_the_day_past_short_months() {
+        let time = 9 * 3600;
+        let (monthly, next) = rule("monthly").next(at(2024, 1, 31, time), 0).unwrap();
+        assert_eq!(next, at(2024, 2, 29, time));
+        let (monthly, next) = monthly.next(next, 0).unwrap();
+        assert_eq!(next, at(2024, 3, 31, time));
+        assert_eq!(monthly.next(next, 0).unwrap().1, at(2024, 4, 30, time));
+
+        let (yearly, next) = rule("yearly").next(at(2024, 2, 29, 0), 0).unwrap();
+        assert_eq!(next, at(2025, 2, 28, 0));
+        assert_eq!(yearly.next(at(2027, 2, 28, 0), 0).unwrap().1, at(2028, 2, 29, 0));
+    }
+
+    #[test]
+    fn weekly_by_day_wraps_around_the_week() {
+        // 2025-01-03 is a Friday
+        let friday = at(2025, 1, 3, 8 * 3600);
+        assert_eq!(rule("FREQ=WEEKLY;BYDAY=MO,FR").next(friday, 0).unwrap().1, at(2025, 1, 6, 8 * 3600));
+        assert_eq!(rule("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR").next(friday, 0).unwrap().1, at(2025, 1, 13, 8 * 3600));
+        assert_eq!(rule("FREQ=WEEKLY;BYDAY=SA,SU").next(friday, 0).unwrap().1, at(2025, 1, 4, 8 * 3600));
+        assert_eq!(rule("FREQ=WEEKLY;BYDAY=FR").next(friday, 0).unwrap().1, at(2025, 1, 10, 8 * 3600));
+    }
+
+    #[test]
+    fn count_and_until_run_out() {
+        let (count, next) = rule("FREQ=DAILY;COUNT=2").next(at(2025, 1, 1, 0), 0).unwrap();
+        assert_eq!((count.count, next), (Some(1), at(2025, 1, 2, 0)));
+        assert_eq!(count.next(next, 0), None);
+
+        let (until, next) = rule("FREQ=DAILY;UNTIL=20250102").next(at(2025, 1, 1, 10 * 3600), 0).unwrap();
+        assert_eq!(next, at(2025, 1, 2, 10 * 3600));
+        assert_eq!(until.next(next, 0), None);
+    }
+
+    #[test]
+    fn skips_occurrences_that_are_overdue() {
+        let now = at(2025, 6, 15, 12 * 3600);
+        assert_eq!(rule("daily").next(at(2020, 1, 1, 9 * 3600), now).unwrap().1, at(2025, 6, 16, 9 * 3600));
+        assert_eq!(rule("FREQ=DAILY;INTERVAL=3").next(at(2025, 6, 1, 0), now).unwrap().1, at(2025, 6, 16, 0));
+        assert_eq!(rule("monthly").next(at(2020, 1, 31, 0), now).unwrap().1, at(2025, 6, 30, 0));
+        assert_eq!(rule("yearly").next(at(2020, 6, 15, 13 * 3600), now).unwrap().1, at(2025, 6, 15, 13 * 3600));
+        assert_eq!(rule("FREQ=DAILY;COUNT=2").next(at(2020, 1, 1, 0), now).unwrap().1, at(2025, 6, 16, 0));
+        assert!(rule("daily").next(-1_000_000_000_000_000, now).is_some());
+    }
+
+    #[test]
+    fn gives_up_on_occurrences_out_of_reach() {
+        for s in ["daily", "weekly", "FREQ=WEEKLY;BYDAY=MO", "monthly", "yearly", "FREQ=YEARLY;INTERVAL=4294967295"] {
+            assert_eq!(rule(s).next(i64::MAX - 10, 0), None, "{s:?}")
+        }
+    }
+}
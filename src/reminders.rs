@@ -0,0 +1,87 @@
+//! A task running alongside [`DbThread`] that fires the reminders of notes once their `remind_at` has passed,
+//! and the `text/event-stream` they are pushed to connected browsers through.
+
+use crate::*;
+
+use std::convert::Infallible;
+
+use tokio::sync::broadcast::error::RecvError;
+
+pub const CHANNEL_CAPACITY: usize = 64;
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Idle proxies and browsers drop silent connections, so a comment is sent every so often.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Reminder {
+    uuid: Uuid,
+    title: Box::<str>,
+    due_at: Option::<UnixTimeStamp>,
+    remind_at: UnixTimeStamp
+}
+
+#[inline]
+fn is_due(note: &Note, now: UnixTimeStamp) -> bool {
+    !note.is_trashed() && note.reminded_at.is_none() && note.remind_at.is_some_and(|remind_at| remind_at <= now)
+}
+
+/// Reminders that came due while the server was down fire on the first check.
+fn fire_due(state: &Server) {
+    let now = unix_now();
+    let uuids = state.notes.iter().filter(|e| is_due(e, now)).map(|e| *e.key()).collect::<Vec::<_>>();
+    for uuid in uuids.iter() {
+        let Some(note) = state.modify_note_if(uuid, |note| is_due(note, now), |note| note.reminded_at = Some(now)) else { continue };
+        // nobody listening is fine, the note still records that its reminder went off
+        _ = state.reminders.send(Reminder {
+            uuid: note.uuid,
+            title: note.title.clone(),
+            due_at: note.due_at,
+            remind_at: note.remind_at.unwrap_or(now)
+        });
+    }
+}
+
+pub fn spawn(state: Data::<Server>, stop: Arc::<AtomicBool>) -> JoinHandle::<()> {
+    actix_rt::spawn(async move {
+        while !stop.load(Ordering::Relaxed) {
+            fire_due(&state);
+            actix_rt::time::sleep(CHECK_INTERVAL).await
+        }
+    })
+}
+
+/// Reminders of the notes the user can see, until the device is logged out.
+#[get("/reminders/events")]
+async fn reminder_events(state: Data::<Server>, session: web::ReqData::<auth::Session>) -> impl Responder {
+    let session = session.into_inner();
+    let streams = (state.reminders.subscribe(), state.shutdown.clone(), Data::clone(&state));
+    let events = futures_util::stream::unfold(streams, move |(mut reminders, mut shutdown, state)| async move {
+        if *shutdown.borrow() { return None }
+        let event = loop {
+            tokio::select! {
+                _ = shutdown.changed() => return None,
+                event = actix_rt::time::timeout(KEEP_ALIVE_INTERVAL, reminders.recv()) => {
+                    if !state.devices.is_active(&session) { return None }
+                    match event {
+                        Ok(Ok(reminder)) if state.get_note_as(session.user, &reminder.uuid, Access::Read).is_ok() => {
+                            break format!("event: reminder\ndata: {data}\n\n", data = json!(reminder))
+                        }
+                        Ok(Ok(..)) => continue,
+                        Ok(Err(RecvError::Lagged(count))) => break format!(": skipped {count} reminder(s)\n\n"),
+                        Ok(Err(RecvError::Closed)) => return None,
+                        Err(..) => break ": keep-alive\n\n".to_owned()
+                    }
+                }
+            }
+        };
+        Some((Ok::<_, Infallible>(web::Bytes::from(event)), (reminders, shutdown, state)))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(reminder_events);
+}
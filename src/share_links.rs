@@ -0,0 +1,183 @@
+//! Public links to single notes, for showing a note to someone without an account. `POST /notes/{uuid}/share`
+//! makes one, and anyone who has it gets a read-only HTML page of the note at `GET /s/{token}`. Links may expire,
+//! and the owner of the note revokes them whenever. Only the SHA-256 of their token is stored, like with devices,
+//! so the URL is only ever shown once, when the link is made.
+//!
+//! Links don't show notes sitting in the trash, and are removed along with their note.
+
+use crate::*;
+
+use actix_web::http::StatusCode;
+
+const COLUMNS: &str = "id, note_uuid, created_at, expires_at";
+
+const STYLE: &str = "\
+body { margin: 0; background: #f5f5f5; color: #222; font-family: \"DM Sans\", system-ui, sans-serif; line-height: 1.5; }
+main { max-width: 720px; margin: 40px auto; padding: 24px 32px; background: #fff; border-radius: 8px; box-shadow: 0 1px 4px rgba(0, 0, 0, 0.1); }
+h1 { margin-top: 0; overflow-wrap: anywhere; }
+.meta { color: #777; font-size: 0.9em; }
+.tag { display: inline-block; margin-right: 6px; padding: 0 8px; background: #e9ecef; border-radius: 10px; font-size: 0.85em; }
+article { overflow-wrap: anywhere; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ddd; padding: 4px 8px; }
+pre { overflow-x: auto; }
+";
+
+#[derive(Serialize)]
+struct ShareLink {
+    id: Uuid,
+    note: Uuid,
+    created_at: UnixTimeStamp,
+    expires_at: Option::<UnixTimeStamp>,
+    /// Only there in the response to making the link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option::<String>
+}
+
+#[derive(Deserialize)]
+struct NewShareLink {
+    /// Never expires if left out.
+    #[serde(default)]
+    expires_at: Option::<UnixTimeStamp>
+}
+
+fn from_row(row: &rusqlite::Row) -> Result::<ShareLink> {
+    Ok(ShareLink {
+        id: Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"),
+        note: Uuid::parse_str(&row.get::<_, String>(1)?).expect("invalid UUID"),
+        created_at: row.get(2)?,
+        expires_at: row.get(3)?,
+        url: None
+    })
+}
+
+#[post("/notes/{uuid}/share")]
+async fn create_link(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, req: HttpRequest, json: Json::<NewShareLink>) -> ApiResult {
+    let NewShareLink { expires_at } = json.into_inner();
+    let now = unix_now();
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(ApiError::Validation(vec![validate::FieldError {
+            field: "expires_at",
+            message: "must be in the future".to_owned()
+        }]))
+    }
+    state.get_note_as(user, &uuid, Access::Owner)?;
+
+    let token = auth::new_token();
+    // the address the server is bound to rather than the Host header, which is up to whoever makes the request
+    let scheme = if state.tls_fingerprint.is_some() { "https" } else { "http" };
+    let url = format!("{scheme}://{addr}/s/{token}", addr = req.app_config().local_addr());
+    let link = ShareLink { id: Uuid::new_v4(), note: *uuid, created_at: now, expires_at, url: Some(url) };
+    state.pool.get()?.execute(
+        "INSERT INTO share_links (id, note_uuid, token_hash, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![link.id.to_string(), link.note.to_string(), auth::hash(&token), link.created_at, link.expires_at]
+    )?;
+    Ok(HttpResponse::Created().json(link))
+}
+
+/// The links to the note, expired ones included.
+#[get("/notes/{uuid}/share")]
+async fn list_links(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_any_note_as(user, &uuid, Access::Owner)?;
+    let conn = state.pool.get()?;
+    let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM share_links WHERE note_uuid = ?1 ORDER BY created_at"))?;
+    let links = stmt.query_map(params![uuid.to_string()], from_row)?.collect::<Result::<Vec::<_>>>()?;
+    Ok(HttpResponse::Ok().json(links))
+}
+
+#[delete("/notes/{uuid}/share/{id}")]
+async fn revoke_link(state: Data::<Server>, user: User, path: web::Path::<(Uuid, Uuid)>) -> ApiResult {
+    let (uuid, id) = path.into_inner();
+    state.get_any_note_as(user, &uuid, Access::Owner)?;
+    let removed = state.pool.get()?.execute(
+        "DELETE FROM share_links WHERE id = ?1 AND note_uuid = ?2",
+        params![id.to_string(), uuid.to_string()]
+    )?;
+    if removed == 0 { return Err(ApiError::NotFound) }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn format_time(time: UnixTimeStamp) -> String {
+    let (year, month, day) = recurrence::civil_from_days(time.div_euclid(recurrence::SECS_PER_DAY));
+    let secs = time.rem_euclid(recurrence::SECS_PER_DAY);
+    format!("{year:04}-{month:02}-{day:02} {h:02}:{m:02} UTC", h = secs / 3600, m = secs / 60 % 60)
+}
+
+fn page(status: StatusCode, title: &str, body: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .insert_header((header::CONTENT_SECURITY_POLICY, "default-src 'none'; style-src 'unsafe-inline'; img-src https: data:"))
+        .insert_header((HeaderName::from_static("x-robots-tag"), "noindex"))
+        .body(format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n\
+            <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n\
+            <meta name=\"robots\" content=\"noindex\">\n<title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n\
+            <body>\n<main>\n{body}</main>\n</body>\n</html>\n",
+            title = escape(title)
+        ))
+}
+
+#[inline]
+fn gone(status: StatusCode, message: &str) -> HttpResponse {
+    page(status, "Internotes", &format!("<h1>Nothing to see here</h1>\n<p class=\"meta\">{message}</p>\n"))
+}
+
+/// The note behind a link, as a page of its own. Open to everyone, the token is all it takes.
+#[get("/s/{token}")]
+pub async fn view(state: Data::<Server>, token: web::Path::<String>) -> ApiResult {
+    let token_hash = auth::hash(&token);
+    let (id, uuid, expires_at) = match state.pool.get()?.query_row(
+        "SELECT id, note_uuid, expires_at FROM share_links WHERE token_hash = ?1",
+        params![token_hash],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Option::<UnixTimeStamp>>(2)?))
+    ) {
+        Ok(link) => link,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            return Ok(gone(StatusCode::NOT_FOUND, "This link doesn't exist, or has been revoked."))
+        }
+        Err(e) => return Err(e.into())
+    };
+    if expires_at.is_some_and(|expires_at| expires_at <= unix_now()) {
+        state.pool.get()?.execute("DELETE FROM share_links WHERE id = ?1", params![id])?;
+        return Ok(gone(StatusCode::GONE, "This link has expired."))
+    }
+    let Some(note) = state.get_note(&Uuid::parse_str(&uuid).expect("invalid UUID")) else {
+        return Ok(gone(StatusCode::NOT_FOUND, "The note behind this link has been deleted."))
+    };
+
+    let tags = note.tags.iter().map(|tag| format!("<span class=\"tag\">{tag}</span>", tag = escape(tag))).collect::<String>();
+    // the description is sanitized HTML already, see [`markdown`]
+    let body = format!(
+        "<h1>{title}</h1>\n<p class=\"meta\">{status} &middot; last modified {modified}</p>\n<p>{tags}</p>\n<article>\n{description}</article>\n",
+        title = escape(&note.title),
+        status = escape(&note.status.0),
+        modified = format_time(note.mod_time),
+        description = note.description_html
+    );
+    Ok(page(StatusCode::OK, &note.title, &body))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(create_link)
+        .service(list_links)
+        .service(revoke_link);
+}
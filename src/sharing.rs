@@ -0,0 +1,271 @@
+//! Owners share notes, or whole notebooks, with other users, who get to read them or to edit them too.
+//! Only the owner of a note trashes it, moves it between notebooks or changes who it's shared with.
+//!
+//! Sharing a note counts as a change to it, so that it shows up in the event streams and the incremental sync of
+//! the users it's shared with. Unsharing it sends them a `deleted` event, and a deletion the next time they sync.
+
+use crate::*;
+
+/// What a user gets to do with a note, each level allows everything the one before it does.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Owner
+}
+
+impl Access {
+    /// Why a user with less than this access was turned away.
+    #[inline]
+    pub fn denied(self) -> &'static str {
+        match self {
+            Self::Read => "this note isn't shared with you",
+            Self::Write => "this note is shared with you read-only",
+            Self::Owner => "only the owner of a note can do this"
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    Write
+}
+
+impl Permission {
+    #[inline]
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write"
+        }
+    }
+
+    #[inline]
+    fn parse(s: &str) -> Self {
+        match s {
+            "read" => Self::Read,
+            "write" => Self::Write,
+            _ => panic!("invalid permission {s:?}")
+        }
+    }
+}
+
+impl From::<Permission> for Access {
+    #[inline]
+    fn from(permission: Permission) -> Self {
+        match permission {
+            Permission::Read => Self::Read,
+            Permission::Write => Self::Write
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Share {
+    user: Uuid,
+    permission: Permission
+}
+
+#[derive(Deserialize)]
+struct ShareRequest {
+    permission: Permission
+}
+
+/// Who notes and notebooks are shared with, kept in memory since every request asks.
+pub struct Shares {
+    /// By note and user.
+    notes: DashMap::<(Uuid, Uuid), Permission>,
+    /// By notebook and user.
+    notebooks: DashMap::<(Uuid, Uuid), Permission>
+}
+
+fn load_table(conn: &Connection, sql: &str) -> Result::<DashMap::<(Uuid, Uuid), Permission>> {
+    conn.prepare(sql)?.query_map([], |row| {
+        Ok((
+            (
+                Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"),
+                Uuid::parse_str(&row.get::<_, String>(1)?).expect("invalid UUID")
+            ),
+            Permission::parse(&row.get::<_, String>(2)?)
+        ))
+    })?.collect()
+}
+
+impl Shares {
+    pub fn load(conn: &Connection) -> Result::<Self> {
+        Ok(Self {
+            notes: load_table(conn, "SELECT note_uuid, user_id, permission FROM note_shares")?,
+            notebooks: load_table(conn, "SELECT notebook_uuid, user_id, permission FROM notebook_shares")?
+        })
+    }
+
+    /// What `user` gets to do with `note`, `None` if they don't get to see it at all.
+    pub fn access(&self, user: User, note: &Note) -> Option::<Access> {
+        if note.owner == Some(user.0) { return Some(Access::Owner) }
+        let shared = self.notes.get(&(note.uuid, user.0)).map(|e| Access::from(*e));
+        let via_notebook = note.notebook.and_then(|notebook| self.notebook_access(user, &notebook));
+        shared.max(via_notebook)
+    }
+
+    #[inline]
+    pub fn notebook_access(&self, user: User, notebook: &Uuid) -> Option::<Access> {
+        self.notebooks.get(&(*notebook, user.0)).map(|e| Access::from(*e))
+    }
+
+    /// Everyone but the owner who gets to see `note`.
+    pub fn readers(&self, note: &Note) -> Vec::<Uuid> {
+        let mut readers = self.notes.iter()
+            .filter(|e| e.key().0 == note.uuid)
+            .map(|e| e.key().1)
+            .chain(note.notebook.iter().flat_map(|notebook| self.notebook_readers(notebook)))
+            .filter(|user| Some(*user) != note.owner)
+            .collect::<Vec::<_>>();
+        readers.sort_unstable();
+        readers.dedup();
+        readers
+    }
+
+    /// Everyone the notebook is shared with.
+    #[inline]
+    pub fn notebook_readers(&self, notebook: &Uuid) -> Vec::<Uuid> {
+        self.notebooks.iter().filter(|e| e.key().0 == *notebook).map(|e| e.key().1).collect()
+    }
+
+    /// Forgets the shares of a removed notebook, the DB drops them along with it.
+    #[inline]
+    pub fn remove_notebook(&self, notebook: &Uuid) {
+        self.notebooks.retain(|(uuid, _), _| uuid != notebook)
+    }
+}
+
+/// Makes sure `user` is someone other than the owner to share with.
+fn check_user(state: &Server, owner: User, user: &Uuid) -> Result::<(), ApiError> {
+    if *user == owner.0 { return Err(ApiError::BadRequest("can't share with the owner".to_owned())) }
+    if !users::exists(state, user)? { return Err(ApiError::NotFound) }
+    Ok(())
+}
+
+#[inline]
+fn list(shares: &DashMap::<(Uuid, Uuid), Permission>, of: &Uuid) -> Vec::<Share> {
+    shares.iter().filter(|e| e.key().0 == *of).map(|e| Share { user: e.key().1, permission: *e.value() }).collect()
+}
+
+/// Publishes the notes as changed, for the users they were just shared with.
+#[inline]
+fn touch(state: &Server, pred: impl Fn(&Note) -> bool) {
+    state.modify_notes_where(|note| !note.is_trashed() && pred(note), |_| {});
+}
+
+/// Tells `user` about the notes they no longer see, out of the ones they could see before.
+pub fn revoke(state: &Server, user: User, seen: Vec::<Arc::<Note>>) -> Result::<(), ApiError> {
+    let conn = state.pool.get()?;
+    for note in seen {
+        if state.get_note(&note.uuid).is_some_and(|note| !state.can_read(user, &note)) {
+            let seq = state.changes.revoke(note.uuid, user);
+            conn.execute(
+                "INSERT OR REPLACE INTO revocations (note_uuid, user_id, seq) VALUES (?1, ?2, ?3)",
+                params![note.uuid.to_string(), user.0.to_string(), seq]
+            )?;
+            state.events.revoke(user, &note)
+        }
+    }
+    Ok(())
+}
+
+#[get("/notes/{uuid}/shares")]
+async fn list_note_shares(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_any_note_as(user, &uuid, Access::Owner)?;
+    Ok(HttpResponse::Ok().json(list(&state.shares.notes, &uuid)))
+}
+
+#[put("/notes/{uuid}/shares/{user}")]
+async fn share_note(state: Data::<Server>, user: User, path: web::Path::<(Uuid, Uuid)>, json: Json::<ShareRequest>) -> ApiResult {
+    let (uuid, with) = path.into_inner();
+    let permission = json.into_inner().permission;
+    state.get_any_note_as(user, &uuid, Access::Owner)?;
+    check_user(&state, user, &with)?;
+    state.pool.get()?.execute(
+        "INSERT OR REPLACE INTO note_shares (note_uuid, user_id, permission) VALUES (?1, ?2, ?3)",
+        params![uuid.to_string(), with.to_string(), permission.as_str()]
+    )?;
+    state.shares.notes.insert((uuid, with), permission);
+    touch(&state, |note| note.uuid == uuid);
+    Ok(HttpResponse::Ok().json(Share { user: with, permission }))
+}
+
+#[delete("/notes/{uuid}/shares/{user}")]
+async fn unshare_note(state: Data::<Server>, user: User, path: web::Path::<(Uuid, Uuid)>) -> ApiResult {
+    let (uuid, with) = path.into_inner();
+    state.get_any_note_as(user, &uuid, Access::Owner)?;
+    let removed = state.pool.get()?.execute(
+        "DELETE FROM note_shares WHERE note_uuid = ?1 AND user_id = ?2",
+        params![uuid.to_string(), with.to_string()]
+    )?;
+    if removed == 0 { return Err(ApiError::NotFound) }
+    let seen = state.live_notes(User(with)).into_iter().filter(|note| note.uuid == uuid).collect();
+    state.shares.notes.remove(&(uuid, with));
+    revoke(&state, User(with), seen)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Makes sure the notebook exists and belongs to `user`.
+fn check_notebook_owner(state: &Server, user: User, notebook: &Uuid) -> Result::<(), ApiError> {
+    match state.pool.get()?.query_row(
+        "SELECT owner = ?2 FROM notebooks WHERE uuid = ?1",
+        params![notebook.to_string(), user.0.to_string()],
+        |row| row.get::<_, Option::<bool>>(0)
+    ) {
+        Ok(Some(true)) => Ok(()),
+        Ok(..) if state.shares.notebook_access(user, notebook).is_some() => Err(ApiError::Forbidden(Access::Owner.denied())),
+        Ok(..) | Err(rusqlite::Error::QueryReturnedNoRows) => Err(ApiError::NotFound),
+        Err(e) => Err(e.into())
+    }
+}
+
+#[get("/notebooks/{uuid}/shares")]
+async fn list_notebook_shares(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    check_notebook_owner(&state, user, &uuid)?;
+    Ok(HttpResponse::Ok().json(list(&state.shares.notebooks, &uuid)))
+}
+
+#[put("/notebooks/{uuid}/shares/{user}")]
+async fn share_notebook(state: Data::<Server>, user: User, path: web::Path::<(Uuid, Uuid)>, json: Json::<ShareRequest>) -> ApiResult {
+    let (uuid, with) = path.into_inner();
+    let permission = json.into_inner().permission;
+    check_notebook_owner(&state, user, &uuid)?;
+    check_user(&state, user, &with)?;
+    state.pool.get()?.execute(
+        "INSERT OR REPLACE INTO notebook_shares (notebook_uuid, user_id, permission) VALUES (?1, ?2, ?3)",
+        params![uuid.to_string(), with.to_string(), permission.as_str()]
+    )?;
+    state.shares.notebooks.insert((uuid, with), permission);
+    touch(&state, |note| note.notebook == Some(uuid));
+    Ok(HttpResponse::Ok().json(Share { user: with, permission }))
+}
+
+#[delete("/notebooks/{uuid}/shares/{user}")]
+async fn unshare_notebook(state: Data::<Server>, user: User, path: web::Path::<(Uuid, Uuid)>) -> ApiResult {
+    let (uuid, with) = path.into_inner();
+    check_notebook_owner(&state, user, &uuid)?;
+    let removed = state.pool.get()?.execute(
+        "DELETE FROM notebook_shares WHERE notebook_uuid = ?1 AND user_id = ?2",
+        params![uuid.to_string(), with.to_string()]
+    )?;
+    if removed == 0 { return Err(ApiError::NotFound) }
+    let seen = state.live_notes(User(with)).into_iter().filter(|note| note.notebook == Some(uuid)).collect();
+    state.shares.notebooks.remove(&(uuid, with));
+    revoke(&state, User(with), seen)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_note_shares)
+        .service(share_note)
+        .service(unshare_note)
+        .service(list_notebook_shares)
+        .service(share_notebook)
+        .service(unshare_notebook);
+}
@@ -0,0 +1,268 @@
+//! The set of statuses a note can be in, their order and colours, and which moves between them are allowed,
+//! all stored in the DB and kept in memory as a [`Workflow`].
+//!
+//! There's one workflow for every user, and renaming or removing a status changes the notes of all of them,
+//! so only the machine running the server gets to change it.
+
+use crate::*;
+use crate::validate::{Validator, FieldError};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StatusDef {
+    pub name: Status,
+    pub position: i64,
+    pub color: Box::<str>,
+    /// Whether notes in this status count as finished.
+    pub done: bool
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Transition {
+    pub from: Status,
+    pub to: Status
+}
+
+#[derive(Default)]
+pub struct Workflow {
+    /// Sorted by position.
+    pub statuses: Vec::<StatusDef>,
+    /// A status without any transitions from it can move into any other status.
+    pub transitions: BTreeSet::<Transition>
+}
+
+impl Workflow {
+    pub fn load(conn: &Connection) -> Result::<Self> {
+        let statuses = conn.prepare("SELECT name, position, color, done FROM statuses ORDER BY position, name")?.query_map([], |row| {
+            Ok(StatusDef {
+                name: Status(row.get(0)?),
+                position: row.get(1)?,
+                color: row.get(2)?,
+                done: row.get(3)?
+            })
+        })?.collect::<Result::<_>>()?;
+        let transitions = conn.prepare("SELECT from_status, to_status FROM status_transitions")?.query_map([], |row| {
+            Ok(Transition { from: Status(row.get(0)?), to: Status(row.get(1)?) })
+        })?.collect::<Result::<_>>()?;
+        Ok(Self { statuses, transitions })
+    }
+
+    #[inline]
+    pub fn get(&self, status: &Status) -> Option::<&StatusDef> {
+        self.statuses.iter().find(|def| def.name == *status)
+    }
+
+    /// Statuses that aren't defined count as not done.
+    #[inline]
+    pub fn is_done(&self, status: &Status) -> bool {
+        self.get(status).is_some_and(|def| def.done)
+    }
+
+    #[inline]
+    fn unknown_status(status: &Status) -> ApiError {
+        ApiError::Validation(vec![FieldError { field: "status", message: format!("status \"{status}\" is not defined") }])
+    }
+
+    /// Fills in the first status of the workflow if `status` is empty, otherwise makes sure it exists.
+    pub fn check_new(&self, status: &mut Status) -> Result::<(), ApiError> {
+        if status.is_empty() {
+            *status = self.statuses.first().map(|def| def.name.clone()).ok_or_else(|| {
+                ApiError::Conflict("there are no statuses defined".to_owned())
+            })?;
+        }
+        if self.get(status).is_none() { return Err(Self::unknown_status(status)) }
+        Ok(())
+    }
+
+    pub fn check_transition(&self, from: &Status, to: &Status) -> Result::<(), ApiError> {
+        if from == to { return Ok(()) }
+        if self.get(to).is_none() { return Err(Self::unknown_status(to)) }
+        let mut allowed = self.transitions.iter().filter(|t| t.from == *from).peekable();
+        if allowed.peek().is_none() || allowed.any(|t| t.to == *to) {
+            Ok(())
+        } else {
+            Err(ApiError::Conflict(format!("moving a note from \"{from}\" to \"{to}\" is not allowed")))
+        }
+    }
+
+    /// The statuses out of `statuses` that aren't defined in the workflow, with how many times each comes up.
+    pub fn unknown_statuses(&self, statuses: impl IntoIterator::<Item = Status>) -> Vec::<(Status, usize)> {
+        let mut unknown = BTreeMap::<Status, usize>::new();
+        statuses.into_iter().filter(|status| self.get(status).is_none()).for_each(|status| {
+            *unknown.entry(status).or_default() += 1
+        });
+        unknown.into_iter().collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct NewStatus {
+    name: Box::<str>,
+    position: Option::<i64>,
+    color: Option::<Box::<str>>,
+    #[serde(default)]
+    done: bool
+}
+
+#[derive(Deserialize)]
+struct StatusPatch {
+    name: Option::<Box::<str>>,
+    position: Option::<i64>,
+    color: Option::<Box::<str>>,
+    done: Option::<bool>
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    /// Where to move notes that are still in the removed status.
+    move_to: Option::<Status>
+}
+
+const DEFAULT_COLOR: &str = "#6c757d";
+
+fn validate_color(v: &mut Validator, color: &str) {
+    let hex = color.strip_prefix('#').unwrap_or_default();
+    if !matches!(hex.len(), 3 | 6) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        v.error("color", "must be a hex colour like #1e90ff")
+    }
+}
+
+#[inline]
+fn reload(state: &Server, conn: &Connection) -> Result::<(), ApiError> {
+    *state.workflow.write().unwrap() = Workflow::load(conn)?;
+    Ok(())
+}
+
+#[get("/statuses")]
+async fn list_statuses(state: Data::<Server>, user: User) -> impl Responder {
+    let notes = state.live_notes(user);
+    let workflow = state.workflow.read().unwrap();
+    let unknown = workflow.unknown_statuses(notes.iter().map(|note| note.status.clone())).into_iter().map(|(status, count)| {
+        json!({"status": status, "count": count})
+    }).collect::<Vec::<_>>();
+    HttpResponse::Ok().json(json!({
+        "statuses": workflow.statuses,
+        "transitions": workflow.transitions,
+        "unknown": unknown
+    }))
+}
+
+#[post("/statuses")]
+async fn create_status(state: Data::<Server>, req: HttpRequest, json: Json::<NewStatus>) -> ApiResult {
+    if !auth::is_host(&req) { return Err(ApiError::Forbidden(auth::HOST_ONLY)) }
+    let NewStatus { mut name, position, color, done } = json.into_inner();
+    let color = color.unwrap_or_else(|| DEFAULT_COLOR.into());
+    let mut v = Validator::default();
+    v.name("name", &mut name, &state.config);
+    validate_color(&mut v, &color);
+    v.finish().map_err(ApiError::Validation)?;
+
+    let conn = state.pool.get()?;
+    let position = match position {
+        Some(position) => position,
+        None => state.workflow.read().unwrap().statuses.last().map_or(0, |def| def.position + 1)
+    };
+    conn.execute(
+        "INSERT INTO statuses (name, position, color, done) VALUES (?1, ?2, ?3, ?4)",
+        params![name, position, color, done]
+    ).map_err(|e| ApiError::from_unique(e, "status", &name))?;
+    reload(&state, &conn)?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/api/v1/statuses/{name}")))
+        .json(StatusDef { name: Status(name), position, color, done }))
+}
+
+#[patch("/statuses/{name}")]
+async fn update_status(state: Data::<Server>, req: HttpRequest, name: web::Path::<String>, json: Json::<StatusPatch>) -> ApiResult {
+    if !auth::is_host(&req) { return Err(ApiError::Forbidden(auth::HOST_ONLY)) }
+    let StatusPatch { name: mut new_name, position, color, done } = json.into_inner();
+    let old = Status(name.into_inner().into());
+    let mut v = Validator::default();
+    if let Some(ref mut new_name) = new_name { v.name("name", new_name, &state.config) }
+    if let Some(ref color) = color { validate_color(&mut v, color) }
+    v.finish().map_err(ApiError::Validation)?;
+
+    let mut conn = state.pool.get()?;
+    let tx = conn.transaction()?;
+    let updated = tx.execute(
+        "UPDATE statuses SET
+            position = COALESCE(?1, position),
+            color = COALESCE(?2, color),
+            done = COALESCE(?3, done)
+        WHERE name = ?4",
+        params![position, color, done, old.0]
+    )?;
+    if updated == 0 { return Err(ApiError::NotFound) }
+    if let Some(ref new_name) = new_name {
+        tx.execute("UPDATE statuses SET name = ?1 WHERE name = ?2", params![new_name, old.0])
+            .map_err(|e| ApiError::from_unique(e, "status", new_name))?;
+    }
+    tx.commit()?;
+    reload(&state, &conn)?;
+
+    if let Some(new_name) = new_name {
+        let new = Status(new_name);
+        state.modify_notes_where(|note| note.status == old, |note| note.status = new.clone());
+    }
+    Ok(HttpResponse::Ok().json(json!({"status": "status updated successfully"})))
+}
+
+#[delete("/statuses/{name}")]
+async fn delete_status(state: Data::<Server>, req: HttpRequest, name: web::Path::<String>, query: web::Query::<DeleteQuery>) -> ApiResult {
+    if !auth::is_host(&req) { return Err(ApiError::Forbidden(auth::HOST_ONLY)) }
+    let status = Status(name.into_inner().into());
+    let in_use = state.notes.iter().any(|e| e.status == status);
+    let move_to = match query.into_inner().move_to {
+        Some(move_to) if move_to == status => return Err(ApiError::BadRequest("can't move notes into the status being removed".to_owned())),
+        Some(move_to) if state.workflow.read().unwrap().get(&move_to).is_none() => return Err(Workflow::unknown_status(&move_to)),
+        Some(move_to) => Some(move_to),
+        None if in_use => return Err(ApiError::Conflict(format!("status \"{status}\" is still in use, pass `move_to` to move its notes"))),
+        None => None
+    };
+
+    let conn = state.pool.get()?;
+    let removed = conn.execute("DELETE FROM statuses WHERE name = ?1", params![status.0])?;
+    if removed == 0 { return Err(ApiError::NotFound) }
+    reload(&state, &conn)?;
+
+    if let Some(move_to) = move_to {
+        state.modify_notes_where(|note| note.status == status, |note| note.status = move_to.clone());
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Replaces the whole set of allowed transitions, an empty set allows everything.
+#[put("/statuses/transitions")]
+async fn set_transitions(state: Data::<Server>, req: HttpRequest, json: Json::<Vec::<Transition>>) -> ApiResult {
+    if !auth::is_host(&req) { return Err(ApiError::Forbidden(auth::HOST_ONLY)) }
+    let transitions = json.into_inner();
+    {
+        let workflow = state.workflow.read().unwrap();
+        if let Some(status) = transitions.iter().flat_map(|t| [&t.from, &t.to]).find(|s| workflow.get(s).is_none()) {
+            return Err(Workflow::unknown_status(status))
+        }
+    }
+
+    let mut conn = state.pool.get()?;
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM status_transitions", [])?;
+    for Transition { from, to } in transitions.iter() {
+        tx.execute(
+            "INSERT OR IGNORE INTO status_transitions (from_status, to_status) VALUES (?1, ?2)",
+            params![from.0, to.0]
+        )?;
+    }
+    tx.commit()?;
+    reload(&state, &conn)?;
+    Ok(HttpResponse::Ok().json(&state.workflow.read().unwrap().transitions))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_statuses)
+        .service(create_status)
+        .service(set_transitions)
+        .service(update_status)
+        .service(delete_status);
+}
@@ -0,0 +1,374 @@
+//! Incremental sync for clients that go offline. Every change to a note gives it the next value of a server-wide
+//! sequence (`seq`), and notes removed for good leave a tombstone taking one too, so `GET /sync?since={seq}` can
+//! tell a client everything that happened since it last synced. Notes moved to the trash are regular upserts,
+//! with `deleted_at` set.
+//!
+//! `POST /sync` takes a batch of changes made offline, each based on the `seq` of the note the client last saw.
+//! Changes to notes that have changed on the server since are reported as conflicts, along with the note as it
+//! is now, and left for the client to resolve. New notes carry a UUID picked by the client, so that resending
+//! a batch whose response got lost doesn't create them twice.
+//!
+//! A change that also carries its `base`, the title and description of the note as the client last saw it, gets
+//! three-way merged into a note that has changed since instead. When the edits overlap, nothing is lost either: the
+//! client's version is kept as a new "conflicted copy" note linking to the original.
+//!
+//! Users only get the notes they can see, see [`crate::sharing`], and the deletions of the notes they owned, or
+//! stopped seeing because they got unshared or removed for good.
+
+use crate::*;
+use crate::validate::{Validate, FieldError};
+
+struct Tombstone {
+    seq: u64,
+    /// Whose note it was, `None` for tombstones older than the owner being kept.
+    owner: Option::<Uuid>,
+    /// Everyone else who could see the note when it got removed.
+    readers: Vec::<Uuid>
+}
+
+/// The server-wide change sequence, the tombstones of notes removed for good, and the notes users stopped seeing.
+pub struct ChangeLog {
+    seq: AtomicU64,
+    tombstones: DashMap::<Uuid, Tombstone>,
+    /// The `seq` at which each user stopped seeing a note of someone else that's still around, by note and user.
+    revocations: DashMap::<(Uuid, Uuid), u64>,
+    /// For telling who else could see a note being removed.
+    shares: Arc::<sharing::Shares>
+}
+
+impl ChangeLog {
+    pub fn load(conn: &Connection, notes: &Notes, shares: Arc::<sharing::Shares>) -> Result::<Self> {
+        let tombstones = conn.prepare("SELECT uuid, seq, owner FROM tombstones")?.query_map([], |row| {
+            Ok((Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"), Tombstone {
+                seq: row.get(1)?,
+                owner: row.get::<_, Option::<String>>(2)?.map(|uuid| Uuid::parse_str(&uuid).expect("invalid UUID")),
+                readers: Vec::new()
+            }))
+        })?.collect::<Result::<DashMap::<_, _>>>()?;
+        let revocations = DashMap::new();
+        conn.prepare("SELECT note_uuid, user_id, seq FROM revocations")?.query_map([], |row| {
+            Ok((
+                Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"),
+                Uuid::parse_str(&row.get::<_, String>(1)?).expect("invalid UUID"),
+                row.get::<_, u64>(2)?
+            ))
+        })?.try_for_each(|row| row.map(|(uuid, user, seq)| match tombstones.get_mut(&uuid) {
+            // stopped seeing it as it got removed
+            Some(mut tombstone) if tombstone.seq == seq => tombstone.readers.push(user),
+            _ => { revocations.insert((uuid, user), seq); }
+        }))?;
+        let seq = notes.iter().map(|e| e.seq)
+            .chain(tombstones.iter().map(|e| e.seq))
+            .chain(revocations.iter().map(|e| *e.value()))
+            .max()
+            .unwrap_or(0);
+        Ok(Self { seq: AtomicU64::new(seq), tombstones, revocations, shares })
+    }
+
+    /// Must be called while the entry of the changed note is locked, see [`changes_since`].
+    #[inline]
+    pub fn next_seq(&self) -> u64 {
+        self.seq.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Called for every note removed for good, returns the `seq` of its tombstone.
+    pub fn tombstone(&self, note: &Note) -> u64 {
+        let readers = self.shares.readers(note);
+        // the tombstone tells them now
+        readers.iter().for_each(|user| { self.revocations.remove(&(note.uuid, *user)); });
+        let entry = self.tombstones.entry(note.uuid);
+        let seq = self.next_seq();
+        entry.insert(Tombstone { seq, owner: note.owner, readers });
+        seq
+    }
+
+    /// Called once `user` stops seeing a note of someone else, returns the `seq` of that.
+    pub fn revoke(&self, uuid: Uuid, user: User) -> u64 {
+        let entry = self.revocations.entry((uuid, user.0));
+        let seq = self.next_seq();
+        entry.insert(seq);
+        seq
+    }
+
+    #[inline]
+    fn is_tombstoned(&self, uuid: &Uuid) -> bool {
+        self.tombstones.contains_key(uuid)
+    }
+
+    /// Whether `uuid` is a note `user` could see that has been removed for good.
+    #[inline]
+    fn removed_for(&self, user: User, uuid: &Uuid) -> bool {
+        self.tombstones.get(uuid).is_some_and(|tombstone| tombstone.is_for(user))
+    }
+}
+
+impl Tombstone {
+    #[inline]
+    fn is_for(&self, user: User) -> bool {
+        self.owner == Some(user.0) || self.readers.contains(&user.0)
+    }
+}
+
+#[derive(Serialize)]
+struct Deletion {
+    uuid: Uuid,
+    seq: u64
+}
+
+/// Everything changed after `since`, along with the `seq` to pass next time.
+///
+/// A `seq` is only ever taken while the entry it's stored in is locked, so reading the counter first and then going
+/// through the entries can't miss a change: one that took a `seq` up to the counter is waited for, and anything later
+/// shows up the next time.
+fn changes_since(state: &Server, user: User, since: u64) -> (u64, Vec::<Arc::<Note>>, Vec::<Deletion>) {
+    let seq = state.changes.seq.load(Ordering::SeqCst);
+    let mut upserts = state.notes.iter()
+        .filter(|e| e.seq > since && state.can_read(user, e))
+        .map(|e| Arc::clone(e.value()))
+        .collect::<Vec::<_>>();
+    upserts.sort_by_key(|note| note.seq);
+    let mut deletions = state.changes.tombstones.iter()
+        .filter(|e| e.seq > since && e.is_for(user))
+        .map(|e| Deletion { uuid: *e.key(), seq: e.seq })
+        .collect::<Vec::<_>>();
+    // unless they got to see the note again since
+    deletions.extend(state.changes.revocations.iter()
+        .filter(|e| *e.value() > since && e.key().1 == user.0)
+        .filter(|e| state.notes.get(&e.key().0).is_none_or(|note| !state.can_read(user, &note)))
+        .map(|e| Deletion { uuid: e.key().0, seq: *e.value() }));
+    deletions.sort_by_key(|deletion| deletion.seq);
+    (seq, upserts, deletions)
+}
+
+#[derive(Deserialize)]
+struct SyncQuery {
+    #[serde(default)]
+    since: u64
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Change {
+    /// Creates the note when `base_seq` is left out, replaces it otherwise.
+    Upsert {
+        uuid: Uuid,
+        #[serde(default)]
+        base_seq: Option::<u64>,
+        #[serde(default)]
+        base: Option::<Base>,
+        note: Box::<json::NoteBody>
+    },
+    /// Moves the note to the trash.
+    Delete {
+        uuid: Uuid,
+        base_seq: u64
+    }
+}
+
+/// What the note looked like at `base_seq`, what to merge against.
+#[derive(Deserialize)]
+struct Base {
+    title: Box::<str>,
+    description: Box::<str>
+}
+
+#[derive(Deserialize)]
+struct SyncRequest {
+    changes: Vec::<Change>
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Outcome {
+    Applied {
+        /// `None` if the note had been removed for good already.
+        note: Option::<Arc::<Note>>
+    },
+    /// The change got merged with the ones made on the server since `base_seq`.
+    Merged {
+        note: Arc::<Note>
+    },
+    /// The change couldn't be merged, so the note was left alone and the change went into `copy`.
+    ConflictedCopy {
+        note: Arc::<Note>,
+        copy: Arc::<Note>
+    },
+    Conflict {
+        reason: &'static str,
+        /// The note as it is on the server, `None` if it has been removed for good.
+        note: Option::<Arc::<Note>>
+    },
+    Rejected {
+        error: &'static str,
+        message: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        fields: Vec::<FieldError>
+    }
+}
+
+impl From::<ApiError> for Outcome {
+    fn from(e: ApiError) -> Self {
+        let (error, message) = (e.kind(), e.to_string());
+        let fields = if let ApiError::Validation(fields) = e { fields } else { Vec::new() };
+        Self::Rejected { error, message, fields }
+    }
+}
+
+#[derive(Serialize)]
+struct ChangeResult {
+    uuid: Uuid,
+    #[serde(flatten)]
+    outcome: Outcome
+}
+
+/// Tells why a change based on `base_seq` can't be applied to the note as it is now.
+fn conflict(state: &Server, user: User, uuid: &Uuid) -> Outcome {
+    if state.changes.removed_for(user, uuid) {
+        return Outcome::Conflict { reason: "removed", note: None }
+    }
+    match state.get_any_note_as(user, uuid, Access::Read) {
+        Ok(note) => Outcome::Conflict { reason: "modified", note: Some(note) },
+        Err(e) => e.into()
+    }
+}
+
+fn create(state: &Server, user: User, uuid: Uuid, mut body: json::NoteBody) -> Result::<Outcome, ApiError> {
+    body.validate(&state.config)?;
+    state.check_note_quota()?;
+    notebooks::check_notebook(state, Some(user.0), body.notebook.as_ref())?;
+    if state.changes.is_tombstoned(&uuid) {
+        return Ok(Outcome::Conflict { reason: "removed", note: None })
+    }
+    let mut note = Note { uuid, owner: Some(user.0), ..Note::default() };
+    body.apply(&mut note);
+    state.workflow.read().unwrap().check_new(&mut note.status)?;
+    Ok(match state.create_note_with_uuid(note) {
+        Some(note) => Outcome::Applied { note: Some(note) },
+        None => Outcome::Conflict { reason: "exists", note: state.get_any_note_as(user, &uuid, Access::Read).ok() }
+    })
+}
+
+/// Merges the title and description of `body` and `note` given what they were both based on, `None` if they overlap.
+fn merge(config: &Config, base: &Base, note: &Note, body: &json::NoteBody) -> Option::<(Box::<str>, Box::<str>)> {
+    let title = diffy::merge(&base.title, &note.title, &body.title).ok()?;
+    let description = diffy::merge(&base.description, &note.description, &body.description).ok()?;
+    // separate edits can still add up to too much
+    if title.chars().count() > config.max_title_len || description.chars().count() > config.max_description_len {
+        return None
+    }
+    Some((title.into(), description.into()))
+}
+
+/// Creates a note of `user` out of a change to `original` that couldn't be merged.
+fn conflicted_copy(state: &Server, user: User, original: &Note, mut body: json::NoteBody) -> Result::<Arc::<Note>, ApiError> {
+    state.check_note_quota()?;
+    const SUFFIX: &str = " (conflicted copy)";
+    let title = body.title.chars().take(state.config.max_title_len.saturating_sub(SUFFIX.len())).collect::<String>();
+    body.title = format!("{title}{SUFFIX}").into();
+    body.description = format!("Conflicted copy of [[{uuid}]]\n\n{description}", uuid = original.uuid, description = body.description).into();
+    let mut note = Note { owner: Some(user.0), ..Note::default() };
+    body.apply(&mut note);
+    // the notebook of someone else the original is in isn't theirs to put notes into
+    if original.owner != note.owner { note.notebook = None }
+    state.workflow.read().unwrap().check_new(&mut note.status)?;
+    Ok(state.create_note(note))
+}
+
+fn replace(state: &Server, user: User, uuid: Uuid, base_seq: u64, base: Option::<Base>, mut body: json::NoteBody) -> Result::<Outcome, ApiError> {
+    body.validate(&state.config)?;
+    let note = match state.get_any_note_as(user, &uuid, Access::Write) {
+        Ok(note) => note,
+        Err(ApiError::NoteNotFound) => return Ok(conflict(state, user, &uuid)),
+        Err(e) => return Err(e)
+    };
+    notebooks::check_move(state, user, &note, body.notebook.as_ref())?;
+    if body.status.is_empty() {
+        body.status = note.status.clone()
+    }
+    state.check_transition(&uuid, &body.status)?;
+
+    // only taken once the note gets modified
+    let mut body = Some(body);
+    loop {
+        let Some(note) = state.notes.get(&uuid).map(|e| Arc::clone(e.value())) else { return Ok(conflict(state, user, &uuid)) };
+        // a trashed note is only changed by restoring it, like everywhere else
+        if note.is_trashed() { return Ok(conflict(state, user, &uuid)) }
+        if note.seq == base_seq {
+            if let Some(note) = state.modify_note_if(&uuid, |note| note.seq == base_seq && !note.is_trashed(), |note| body.take().unwrap().apply(note)) {
+                return Ok(Outcome::Applied { note: Some(note) })
+            }
+            continue
+        }
+        let Some(ref base) = base else { return Ok(conflict(state, user, &uuid)) };
+
+        let Some((title, description)) = merge(&state.config, base, &note, body.as_ref().unwrap()) else {
+            let copy = conflicted_copy(state, user, &note, body.take().unwrap())?;
+            return Ok(Outcome::ConflictedCopy { note, copy })
+        };
+        if let Some(note) = state.modify_note_if(&uuid, |now| now.seq == note.seq, |note| {
+            let mut body = body.take().unwrap();
+            (body.title, body.description) = (title, description);
+            body.apply(note)
+        }) {
+            return Ok(Outcome::Merged { note })
+        }
+        // changed again in the meantime, merge with that
+    }
+}
+
+fn delete(state: &Server, user: User, uuid: Uuid, base_seq: u64) -> Outcome {
+    match state.get_any_note_as(user, &uuid, Access::Owner) {
+        Ok(..) => {}
+        // the note might be gone for good, which is fine
+        Err(ApiError::NoteNotFound) if !state.notes.contains_key(&uuid) => {}
+        Err(e) => return e.into()
+    }
+    let now = unix_now();
+    if let Some(note) = state.modify_note_if(&uuid, |note| note.seq == base_seq && !note.is_trashed(), |note| note.deleted_at = Some(now)) {
+        return Outcome::Applied { note: Some(note) }
+    }
+    // deleting what's already gone is fine
+    if state.changes.removed_for(user, &uuid) {
+        return Outcome::Applied { note: None }
+    }
+    match state.notes.get(&uuid).map(|e| Arc::clone(e.value())) {
+        Some(note) if note.is_trashed() => Outcome::Applied { note: Some(note) },
+        _ => conflict(state, user, &uuid)
+    }
+}
+
+#[get("/sync")]
+async fn pull(state: Data::<Server>, user: User, query: web::Query::<SyncQuery>) -> ApiResult {
+    let (seq, upserts, deletions) = changes_since(&state, user, query.since);
+    Ok(HttpResponse::Ok().json(json!({
+        "seq": seq,
+        "upserts": upserts,
+        "deletions": deletions
+    })))
+}
+
+/// Applies the changes in order, every one of them gets a result whether the others went through or not.
+#[post("/sync")]
+async fn push(state: Data::<Server>, user: User, json: Json::<SyncRequest>) -> ApiResult {
+    let results = json.into_inner().changes.into_iter().map(|change| match change {
+        Change::Upsert { uuid, base_seq: None, note, .. } => ChangeResult {
+            uuid,
+            outcome: create(&state, user, uuid, *note).unwrap_or_else(Outcome::from)
+        },
+        Change::Upsert { uuid, base_seq: Some(base_seq), base, note } => ChangeResult {
+            uuid,
+            outcome: replace(&state, user, uuid, base_seq, base, *note).unwrap_or_else(Outcome::from)
+        },
+        Change::Delete { uuid, base_seq } => ChangeResult { uuid, outcome: delete(&state, user, uuid, base_seq) }
+    }).collect::<Vec::<_>>();
+    Ok(HttpResponse::Ok().json(json!({
+        "seq": state.changes.seq.load(Ordering::SeqCst),
+        "results": results
+    })))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(pull)
+        .service(push);
+}
@@ -0,0 +1,98 @@
+//! Tags live on the cached [`Note`]s, `tags` and `note_tags` tables are rewritten from them on every flush.
+
+use crate::*;
+
+use std::collections::BTreeMap;
+
+pub fn load_note_tags(conn: &Connection, notes: &Notes) -> Result::<()> {
+    let mut stmt = conn.prepare("SELECT note_tags.note_uuid, tags.name FROM note_tags JOIN tags ON tags.id = note_tags.tag_id ORDER BY tags.name")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let uuid = Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID");
+        if let Some(mut note) = notes.get_mut(&uuid) {
+            Arc::make_mut(&mut note).tags.push(row.get(1)?)
+        }
+    }
+    Ok(())
+}
+
+pub fn save_note_tags(conn: &Connection, note: &Note) -> Result::<()> {
+    let uuid = note.uuid.to_string();
+    conn.execute("DELETE FROM note_tags WHERE note_uuid = ?1", params![uuid])?;
+    for tag in note.tags.iter() {
+        conn.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", params![tag])?;
+        conn.execute(
+            "INSERT OR IGNORE INTO note_tags (note_uuid, tag_id) SELECT ?1, id FROM tags WHERE name = ?2",
+            params![uuid, tag]
+        )?;
+    }
+    Ok(())
+}
+
+#[inline]
+pub fn remove_unused_tags(conn: &Connection) -> db::Result {
+    conn.execute("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM note_tags)", [])
+}
+
+#[derive(Serialize)]
+struct Tag<'a> {
+    name: &'a str,
+    count: usize
+}
+
+#[derive(Deserialize)]
+struct Merge {
+    into: Box::<str>
+}
+
+#[inline]
+fn tag_exists(state: &Server, user: User, name: &str) -> bool {
+    state.notes.iter().any(|e| e.owner == Some(user.0) && e.tags.iter().any(|tag| **tag == *name))
+}
+
+/// Replaces `from` with `into` on every note of `user` tagged with it, a note that already had `into` just loses
+/// `from`. Notes shared with them keep their tags.
+fn retag(state: &Server, user: User, from: &str, into: Box::<str>) -> usize {
+    state.modify_notes_where(|note| note.owner == Some(user.0) && note.tags.iter().any(|tag| **tag == *from), |note| {
+        note.tags.retain(|tag| **tag != *from && *tag != into);
+        note.tags.push(Box::clone(&into));
+        note.tags.sort_unstable()
+    })
+}
+
+#[get("/tags")]
+async fn list_tags(state: Data::<Server>, user: User) -> impl Responder {
+    let notes = state.live_notes(user);
+    let mut counts = BTreeMap::<&str, usize>::new();
+    notes.iter().flat_map(|note| note.tags.iter()).for_each(|tag| *counts.entry(tag).or_default() += 1);
+    let tags = counts.into_iter().map(|(name, count)| Tag { name, count }).collect::<Vec::<_>>();
+    HttpResponse::Ok().json(tags)
+}
+
+#[post("/tags/{name}/rename")]
+async fn rename_tag(state: Data::<Server>, user: User, name: web::Path::<String>, json: Json::<json::Name>) -> ApiResult {
+    let mut new_name = json.into_inner().name;
+    validate::tag(&mut new_name, &state.config)?;
+    if !tag_exists(&state, user, &name) { return Err(ApiError::NotFound) }
+    if *new_name != *name && tag_exists(&state, user, &new_name) {
+        return Err(ApiError::Conflict(format!("tag {new_name:?} already exists, merge into it instead")))
+    }
+    let count = retag(&state, user, &name, new_name);
+    Ok(HttpResponse::Ok().json(json!({"status": "tag renamed successfully", "notes": count})))
+}
+
+#[post("/tags/{name}/merge")]
+async fn merge_tag(state: Data::<Server>, user: User, name: web::Path::<String>, json: Json::<Merge>) -> ApiResult {
+    let mut into = json.into_inner().into;
+    validate::tag(&mut into, &state.config)?;
+    if !tag_exists(&state, user, &name) { return Err(ApiError::NotFound) }
+    let count = retag(&state, user, &name, into);
+    Ok(HttpResponse::Ok().json(json!({"status": "tag merged successfully", "notes": count})))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_tags)
+        .service(rename_tag)
+        .service(merge_tag);
+}
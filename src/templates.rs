@@ -0,0 +1,255 @@
+//! Templates prefill new notes, `POST /new-note?template={uuid}` (or `POST /api/v1/notes?template={uuid}`) fills
+//! every field the request leaves empty from the template.
+//!
+//! The title and description of a template may contain placeholders, expanded when a note is made from it:
+//! `{{date}}`, `{{time}}`, `{{datetime}}`, `{{year}}`, `{{month}}` and `{{day}}` (in UTC), and `{{counter}}`,
+//! the number of notes made from the template so far, this one included.
+//!
+//! Every user has templates of their own.
+
+use crate::*;
+use crate::validate::{Validator, FieldError};
+
+#[derive(Serialize, Deserialize)]
+struct Template {
+    #[serde(skip_deserializing)]
+    uuid: Uuid,
+    name: Box::<str>,
+    #[serde(default)]
+    title: Box::<str>,
+    #[serde(default)]
+    description: Box::<str>,
+    #[serde(default)]
+    tags: Vec::<Box::<str>>,
+    /// The first status of the workflow if left out.
+    #[serde(default)]
+    status: Option::<Status>,
+    #[serde(default)]
+    notebook: Option::<Uuid>,
+    #[serde(skip_deserializing)]
+    counter: i64
+}
+
+#[derive(Deserialize)]
+pub struct TemplateQuery {
+    template: Option::<Uuid>
+}
+
+const COLUMNS: &str = "uuid, name, title, description, tags, status, notebook, counter";
+
+fn from_row(row: &rusqlite::Row) -> Result::<Template> {
+    Ok(Template {
+        uuid: Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"),
+        name: row.get(1)?,
+        title: row.get(2)?,
+        description: row.get(3)?,
+        tags: serde_json::from_str(&row.get::<_, String>(4)?).expect("invalid template tags"),
+        status: row.get::<_, Option::<Box::<str>>>(5)?.map(Status),
+        notebook: row.get::<_, Option::<String>>(6)?.map(|uuid| Uuid::parse_str(&uuid).expect("invalid UUID")),
+        counter: row.get(7)?
+    })
+}
+
+fn expand(text: &str, now: UnixTimeStamp, counter: i64) -> Box::<str> {
+    if !text.contains("{{") { return text.into() }
+    let (year, month, day) = recurrence::civil_from_days(now.div_euclid(recurrence::SECS_PER_DAY));
+    let secs = now.rem_euclid(recurrence::SECS_PER_DAY);
+    let (date, time) = (format!("{year:04}-{month:02}-{day:02}"), format!("{h:02}:{m:02}", h = secs / 3600, m = secs / 60 % 60));
+
+    let placeholder = |name: &str| Some(match name.trim() {
+        "date" => date.clone(),
+        "time" => time.clone(),
+        "datetime" => format!("{date} {time}"),
+        "year" => format!("{year:04}"),
+        "month" => format!("{month:02}"),
+        "day" => format!("{day:02}"),
+        "counter" => counter.to_string(),
+        _ => return None
+    });
+
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match rest[2..].split_once("}}").and_then(|(name, after)| Some((placeholder(name)?, after))) {
+            Some((value, after)) => {
+                expanded.push_str(&value);
+                rest = after
+            }
+            // not a placeholder, leave it be
+            None => {
+                expanded.push_str("{{");
+                rest = &rest[2..]
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded.into()
+}
+
+/// Fills the fields of `note` that were left empty from the template and runs `check` on it, bumping the counter of
+/// the template only once the note passes, so that notes turned away don't use up numbers.
+pub fn apply(
+    state: &Server,
+    user: User,
+    query: &TemplateQuery,
+    note: &mut Note,
+    check: impl Fn(&mut Note) -> Result::<(), ApiError>
+) -> Result::<(), ApiError> {
+    let Some(uuid) = query.template else { return check(note) };
+    // the counter only moves if nobody made a note from the template meanwhile, otherwise it's filled in again
+    loop {
+        let template = match state.pool.get()?.query_row(
+            &format!("SELECT {COLUMNS} FROM templates WHERE uuid = ?1 AND owner = ?2"),
+            params![uuid.to_string(), user.0.to_string()],
+            from_row
+        ) {
+            Ok(template) => template,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ApiError::Validation(vec![FieldError {
+                field: "template",
+                message: format!("template {uuid} does not exist")
+            }])),
+            Err(e) => return Err(e.into())
+        };
+
+        let (now, counter) = (unix_now(), template.counter + 1);
+        let mut filled = note.clone();
+        if filled.title.trim().is_empty() { filled.title = expand(&template.title, now, counter) }
+        if filled.description.trim().is_empty() { filled.description = expand(&template.description, now, counter) }
+        if filled.tags.is_empty() { filled.tags = template.tags }
+        if filled.status.is_empty() { filled.status = template.status.unwrap_or_default() }
+        if filled.notebook.is_none() { filled.notebook = template.notebook }
+        check(&mut filled)?;
+
+        let bumped = state.pool.get()?.execute(
+            "UPDATE templates SET counter = ?3 WHERE uuid = ?1 AND owner = ?2 AND counter = ?4",
+            params![uuid.to_string(), user.0.to_string(), counter, template.counter]
+        )?;
+        if bumped == 1 {
+            *note = filled;
+            return Ok(())
+        }
+    }
+}
+
+fn validate_template(state: &Server, user: User, template: &mut Template) -> Result::<(), ApiError> {
+    let ref config = state.config;
+    let mut v = Validator::default();
+    v.name("name", &mut template.name, config);
+    v.text("title", &mut template.title, config.max_title_len, false);
+    v.description(&mut template.description, config);
+    v.tags(&mut template.tags, config);
+    if let Some(ref status) = template.status {
+        if state.workflow.read().unwrap().get(status).is_none() {
+            v.error("status", format!("status \"{status}\" is not defined"))
+        }
+    }
+    v.finish().map_err(ApiError::Validation)?;
+    notebooks::check_notebook(state, Some(user.0), template.notebook.as_ref())
+}
+
+#[get("/templates")]
+async fn list_templates(state: Data::<Server>, user: User) -> ApiResult {
+    let conn = state.pool.get()?;
+    let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM templates WHERE owner = ?1 ORDER BY name"))?;
+    let templates = stmt.query_map(params![user.0.to_string()], from_row)?.collect::<Result::<Vec::<_>>>()?;
+    Ok(HttpResponse::Ok().json(templates))
+}
+
+#[get("/templates/{uuid}")]
+async fn get_template(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    match state.pool.get()?.query_row(
+        &format!("SELECT {COLUMNS} FROM templates WHERE uuid = ?1 AND owner = ?2"),
+        params![uuid.to_string(), user.0.to_string()],
+        from_row
+    ) {
+        Ok(template) => Ok(HttpResponse::Ok().json(template)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(ApiError::NotFound),
+        Err(e) => Err(e.into())
+    }
+}
+
+#[post("/templates")]
+async fn create_template(state: Data::<Server>, user: User, json: Json::<Template>) -> ApiResult {
+    let mut template = json.into_inner();
+    validate_template(&state, user, &mut template)?;
+    template.uuid = Uuid::new_v4();
+    state.pool.get()?.execute(
+        "INSERT INTO templates (uuid, name, title, description, tags, status, notebook, owner) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            template.uuid.to_string(), template.name, template.title, template.description, json!(template.tags).to_string(),
+            template.status.as_ref().map(|status| &status.0), template.notebook.map(|u| u.to_string()), user.0.to_string()
+        ]
+    ).map_err(|e| ApiError::from_unique(e, "template", &template.name))?;
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/api/v1/templates/{uuid}", uuid = template.uuid)))
+        .json(template))
+}
+
+/// Replaces everything but the counter.
+#[put("/templates/{uuid}")]
+async fn replace_template(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>, json: Json::<Template>) -> ApiResult {
+    let mut template = json.into_inner();
+    validate_template(&state, user, &mut template)?;
+    template.uuid = *uuid;
+    template.counter = match state.pool.get()?.query_row(
+        "UPDATE templates SET name = ?1, title = ?2, description = ?3, tags = ?4, status = ?5, notebook = ?6 WHERE uuid = ?7 AND owner = ?8 RETURNING counter",
+        params![
+            template.name, template.title, template.description, json!(template.tags).to_string(),
+            template.status.as_ref().map(|status| &status.0), template.notebook.map(|u| u.to_string()), uuid.to_string(), user.0.to_string()
+        ],
+        |row| row.get(0)
+    ) {
+        Ok(counter) => counter,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ApiError::NotFound),
+        Err(e) => return Err(ApiError::from_unique(e, "template", &template.name))
+    };
+    Ok(HttpResponse::Ok().json(template))
+}
+
+#[delete("/templates/{uuid}")]
+async fn delete_template(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    let removed = state.pool.get()?.execute(
+        "DELETE FROM templates WHERE uuid = ?1 AND owner = ?2",
+        params![uuid.to_string(), user.0.to_string()]
+    )?;
+    if removed == 0 { return Err(ApiError::NotFound) }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_templates)
+        .service(get_template)
+        .service(create_template)
+        .service(replace_template)
+        .service(delete_template);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2025-01-02 13:45:30 UTC
+    const NOW: UnixTimeStamp = 1735825530;
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!(&*expand("{{date}} {{ time }} #{{counter}}", NOW, 7), "2025-01-02 13:45 #7");
+        assert_eq!(&*expand("{{datetime}}: {{year}}/{{month}}/{{day}}", NOW, 1), "2025-01-02 13:45: 2025/01/02");
+        assert_eq!(&*expand("{{counter}}{{counter}}", NOW, 12), "1212");
+        assert_eq!(&*expand("no placeholders", NOW, 1), "no placeholders");
+    }
+
+    #[test]
+    fn leaves_unknown_and_unterminated_placeholders_be() {
+        assert_eq!(&*expand("{{unknown}} {{date}}", NOW, 1), "{{unknown}} 2025-01-02");
+        assert_eq!(&*expand("{{date", NOW, 1), "{{date");
+        assert_eq!(&*expand("{{date}", NOW, 1), "{{date}");
+        assert_eq!(&*expand("{{ {{date}}", NOW, 1), "{{ 2025-01-02");
+        assert_eq!(&*expand("{{{date}}}", NOW, 1), "{{{date}}}");
+        assert_eq!(&*expand("}} {{}} {{", NOW, 1), "}} {{}} {{");
+        assert_eq!(&*expand("ünï{{cöunter}}{{counter}}", NOW, 3), "ünï{{cöunter}}3");
+    }
+}
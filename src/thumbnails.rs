@@ -0,0 +1,226 @@
+//! Downscaled JPEG copies of image attachments, so that listing a note doesn't pull full-size phone photos.
+//! Thumbnails are made in the background right after an upload (or on the first request for one that's missing),
+//! and cached as `<hash>.thumb-<size>.jpg` next to the attached file.
+
+use crate::*;
+use crate::image_write::write_jpg_to_memory;
+
+use std::fs;
+use std::path::PathBuf;
+
+use actix_web::{HttpRequest, mime};
+use actix_files::NamedFile;
+
+/// Sizes a thumbnail can be requested at, in pixels along its longer side.
+pub const SIZES: &[u32] = &[128, 256, 512];
+const DEFAULT_SIZE: u32 = 256;
+const JPEG_QUALITY: i32 = 80;
+/// PNGs can't be decoded at a lower scale like JPEGs can, so decoding one takes this much memory at most.
+const MAX_PNG_DECODING_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct ThumbQuery {
+    size: Option::<u32>
+}
+
+/// 8-bit RGB pixels.
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec::<u8>
+}
+
+#[inline]
+pub fn is_supported(content_type: &str) -> bool {
+    matches!(content_type, "image/jpeg" | "image/png")
+}
+
+#[inline(always)]
+fn thumbnail_path(hash: &str, size: u32) -> PathBuf {
+    attachments::file_path(&format!("{hash}.thumb-{size}.jpg"))
+}
+
+/// Removes the thumbnails of the file with the contents `hash`.
+pub fn remove(hash: &str) {
+    SIZES.iter().map(|&size| thumbnail_path(hash, size)).filter(|path| path.exists()).for_each(|path| {
+        if let Err(e) = fs::remove_file(&path) {
+            eprintln!("could not remove thumbnail: {path}: {e}", path = path.display())
+        }
+    })
+}
+
+/// The orientation tag of TIFF-structured EXIF data, 1 (upright) if there's none.
+fn exif_orientation(exif: &[u8]) -> u16 {
+    let le = match exif.get(..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => return 1
+    };
+    let u16_at = |i: usize| exif.get(i..i + 2).map(|b| if le { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) });
+    let u32_at = |i: usize| exif.get(i..i + 4).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if le { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) }
+    });
+
+    let Some(ifd) = u32_at(4).map(|ifd| ifd as usize) else { return 1 };
+    let count = u16_at(ifd).unwrap_or(0) as usize;
+    (0..count).map(|i| ifd + 2 + i * 12).find(|&entry| u16_at(entry) == Some(0x0112))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+fn decode_jpeg(bytes: &[u8]) -> Result::<(Image, u16), String> {
+    use jpeg_decoder::PixelFormat;
+
+    let max = *SIZES.last().unwrap() as u16;
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    decoder.scale(max, max).map_err(|e| e.to_string())?;
+    let data = decoder.decode().map_err(|e| e.to_string())?;
+    let info = decoder.info().ok_or("missing image info")?;
+    let pixels = match info.pixel_format {
+        PixelFormat::RGB24 => data,
+        PixelFormat::L8 => data.iter().flat_map(|&l| [l; 3]).collect(),
+        // big-endian, keep the high byte
+        PixelFormat::L16 => data.chunks_exact(2).flat_map(|l| [l[0]; 3]).collect(),
+        PixelFormat::CMYK32 => data.chunks_exact(4).flat_map(|p| {
+            let k = 255 - p[3] as u32;
+            [0, 1, 2].map(|i| ((255 - p[i] as u32) * k / 255) as u8)
+        }).collect()
+    };
+    let orientation = decoder.exif_data().map_or(1, exif_orientation);
+    Ok((Image { width: info.width as _, height: info.height as _, pixels }, orientation))
+}
+
+fn decode_png(bytes: &[u8]) -> Result::<Image, String> {
+    use png::ColorType;
+
+    let mut decoder = png::Decoder::new_with_limits(bytes, png::Limits { bytes: MAX_PNG_DECODING_BYTES });
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    data.truncate(info.buffer_size());
+
+    // transparent pixels are put on white
+    let over_white = |c: u8, a: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+    let pixels = match info.color_type {
+        ColorType::Rgb => data,
+        ColorType::Rgba => data.chunks_exact(4).flat_map(|p| [0, 1, 2].map(|i| over_white(p[i], p[3]))).collect(),
+        ColorType::Grayscale => data.iter().flat_map(|&l| [l; 3]).collect(),
+        ColorType::GrayscaleAlpha => data.chunks_exact(2).flat_map(|p| [over_white(p[0], p[1]); 3]).collect(),
+        ColorType::Indexed => return Err("unexpected indexed colours".to_owned())
+    };
+    Ok(Image { width: info.width as _, height: info.height as _, pixels })
+}
+
+impl Image {
+    /// Shrinks the image to fit in a `size` by `size` square by averaging every pixel it covers, never enlarges it.
+    fn downscale(&self, size: usize) -> Self {
+        let scale = (size as f64 / self.width.max(self.height) as f64).min(1.0);
+        let width = ((self.width as f64 * scale).round() as usize).max(1);
+        let height = ((self.height as f64 * scale).round() as usize).max(1);
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            let (y0, y1) = (y * self.height / height, ((y + 1) * self.height / height).max(y * self.height / height + 1));
+            for x in 0..width {
+                let (x0, x1) = (x * self.width / width, ((x + 1) * self.width / width).max(x * self.width / width + 1));
+                let mut sum = [0u32; 3];
+                for sy in y0..y1 {
+                    for p in self.pixels[(sy * self.width + x0) * 3..(sy * self.width + x1) * 3].chunks_exact(3) {
+                        sum.iter_mut().zip(p).for_each(|(sum, &c)| *sum += c as u32)
+                    }
+                }
+                let count = ((y1 - y0) * (x1 - x0)) as u32;
+                pixels.extend(sum.map(|sum| (sum / count) as u8))
+            }
+        }
+        Self { width, height, pixels }
+    }
+
+    /// Turns the image upright according to an EXIF orientation.
+    fn orient(self, orientation: u16) -> Self {
+        if orientation == 1 { return self }
+        let (w, h) = (self.width, self.height);
+        let (width, height) = if orientation >= 5 { (h, w) } else { (w, h) };
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = match orientation {
+                    2 => (w - 1 - x, y),
+                    3 => (w - 1 - x, h - 1 - y),
+                    4 => (x, h - 1 - y),
+                    5 => (y, x),
+                    6 => (y, h - 1 - x),
+                    7 => (w - 1 - y, h - 1 - x),
+                    _ => (w - 1 - y, x)
+                };
+                pixels.extend_from_slice(&self.pixels[(sy * w + sx) * 3..][..3])
+            }
+        }
+        Self { width, height, pixels }
+    }
+}
+
+/// Makes every size of thumbnail for the file with the contents `hash`, blocks for a while.
+pub fn generate(hash: &str, content_type: &str) -> Result::<(), String> {
+    let bytes = fs::read(attachments::file_path(hash)).map_err(|e| e.to_string())?;
+    let (image, orientation) = match content_type {
+        "image/jpeg" => decode_jpeg(&bytes)?,
+        "image/png" => (decode_png(&bytes)?, 1),
+        _ => return Err(format!("can't make thumbnails of {content_type}"))
+    };
+
+    for &size in SIZES.iter() {
+        let thumbnail = image.downscale(size as _).orient(orientation);
+        let jpeg = write_jpg_to_memory(&thumbnail.pixels, thumbnail.width, thumbnail.height, JPEG_QUALITY)
+            .map_err(|_| "could not encode thumbnail".to_owned())?;
+
+        let path = thumbnail_path(hash, size);
+        let tmp_path = path.with_extension(format!("tmp-{uuid}", uuid = Uuid::new_v4()));
+        fs::write(&tmp_path, jpeg).and_then(|_| fs::rename(&tmp_path, &path)).map_err(|e| {
+            _ = fs::remove_file(&tmp_path);
+            e.to_string()
+        })?
+    }
+    Ok(())
+}
+
+/// Makes the thumbnails of a fresh upload without holding up the response.
+pub fn spawn_generate(hash: Box::<str>, content_type: Box::<str>) {
+    if !is_supported(&content_type) { return }
+    actix_rt::task::spawn_blocking(move || {
+        if let Err(e) = generate(&hash, &content_type) {
+            eprintln!("could not make thumbnails of attachment: {hash}: {e}")
+        }
+    });
+}
+
+#[get("/attachments/{id}/thumb")]
+async fn get_thumbnail(state: Data::<Server>, user: User, id: web::Path::<Uuid>, query: web::Query::<ThumbQuery>, req: HttpRequest) -> ApiResult {
+    let size = query.size.unwrap_or(DEFAULT_SIZE);
+    if !SIZES.contains(&size) {
+        return Err(ApiError::BadRequest(format!("size must be one of {SIZES:?}")))
+    }
+    let attachment = attachments::get_attachment(&*state.pool.get()?, &id)?.ok_or(ApiError::NotFound)?;
+    attachments::check_access(&state, user, &attachment, Access::Read)?;
+    if !is_supported(&attachment.content_type) {
+        return Err(ApiError::BadRequest(format!("can't make thumbnails of {content_type}", content_type = attachment.content_type)))
+    }
+
+    let path = thumbnail_path(&attachment.hash, size);
+    if !path.exists() {
+        let (hash, content_type) = (attachment.hash.clone(), attachment.content_type.clone());
+        web::block(move || generate(&hash, &content_type)).await
+            .map_err(|e| ApiError::Internal(e.to_string()))?
+            .map_err(|e| ApiError::BadRequest(format!("could not make thumbnail: {e}")))?
+    }
+
+    let file = NamedFile::open_async(path).await.map_err(|e| ApiError::Internal(format!("could not open thumbnail: {e}")))?;
+    Ok(file.set_content_type(mime::IMAGE_JPEG).into_response(&req))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_thumbnail);
+}
@@ -0,0 +1,100 @@
+//! HTTPS, turned on with `INTERNOTES_TLS=true`, which also gets browsers to speak HTTP/2. The certificate is read
+//! from `INTERNOTES_TLS_CERT` and `INTERNOTES_TLS_KEY` if they're set, otherwise a self-signed one is made on the
+//! first run and kept next to the database, along with the IP it was made for. It's made again whenever the machine
+//! turns up with another IP, since browsers would turn it down anyway.
+//!
+//! Nothing vouches for a self-signed certificate, so the QR code carries the SHA-256 fingerprint of it as well, for
+//! phones to pin it when pairing.
+
+use crate::*;
+
+use std::{fs, io, path::Path};
+
+use rustls::{ServerConfig, pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject}};
+use sha2::{Digest, Sha256};
+
+pub const CERT_FILE_PATH: &str = "internotes-cert.pem";
+pub const KEY_FILE_PATH: &str = "internotes-key.pem";
+/// The IP the self-signed certificate was made for.
+pub const CERT_IP_FILE_PATH: &str = "internotes-cert.ip";
+
+pub struct Tls {
+    pub config: ServerConfig,
+    /// SHA-256 of the DER of the leaf certificate, in hex.
+    pub fingerprint: Box::<str>
+}
+
+#[inline]
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {e}", path = path.display()))
+}
+
+#[inline]
+fn missing(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{path}: no such file", path = path.display()))
+}
+
+/// Makes a self-signed certificate good for `local_ip` (and `localhost`), writing it to `cert_path` and `key_path`,
+/// and the IP to `ip_path`.
+fn generate(local_ip: IpAddr, cert_path: &Path, key_path: &Path, ip_path: &Path) -> io::Result::<()> {
+    let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(vec![local_ip.to_string(), "localhost".to_owned()])
+        .map_err(io::Error::other)?;
+
+    // whatever is left of the last one goes, the key is made anew rather than written over
+    for path in [cert_path, key_path, ip_path] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // the key is nobody's business but the server's
+    #[cfg(unix)] std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    io::Write::write_all(&mut options.open(key_path)?, signing_key.serialize_pem().as_bytes())?;
+    fs::write(cert_path, cert.pem())?;
+    fs::write(ip_path, local_ip.to_string())?;
+
+    println!("[INFO] generated a self-signed certificate at {cert_path}", cert_path = cert_path.display());
+    Ok(())
+}
+
+/// Loads the certificate to serve HTTPS with, making one first if there isn't one yet.
+pub fn load(config: &Config, local_ip: IpAddr) -> io::Result::<Tls> {
+    let (cert_path, key_path) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert_path), Some(key_path)) => {
+            if let Some(path) = [cert_path, key_path].into_iter().find(|path| !path.exists()) { return Err(missing(path)) }
+            (cert_path.as_path(), key_path.as_path())
+        }
+        (None, None) => {
+            let (cert_path, key_path, ip_path) = (Path::new(CERT_FILE_PATH), Path::new(KEY_FILE_PATH), Path::new(CERT_IP_FILE_PATH));
+            let issued_for = fs::read_to_string(ip_path).ok().and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+            if let Some(path) = [cert_path, key_path].into_iter().find(|path| !path.exists()) {
+                println!("[INFO] {path} is missing, making a new certificate", path = path.display());
+                generate(local_ip, cert_path, key_path, ip_path)?
+            } else if issued_for != Some(local_ip) {
+                println!("[INFO] the certificate isn't made for {local_ip}, making a new one");
+                generate(local_ip, cert_path, key_path, ip_path)?
+            }
+            (cert_path, key_path)
+        }
+        _ => return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{prefix}TLS_CERT and {prefix}TLS_KEY have to be set together", prefix = config::ENV_PREFIX)
+        ))
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| invalid(cert_path, e))?
+        .collect::<Result::<Vec::<_>, _>>()
+        .map_err(|e| invalid(cert_path, e))?;
+    let Some(leaf) = certs.first() else { return Err(invalid(cert_path, "no certificate found")) };
+    let fingerprint = Sha256::digest(leaf).iter().map(|b| format!("{b:02x}")).collect();
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| invalid(key_path, e))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(cert_path, e))?;
+    Ok(Tls { config, fingerprint })
+}
@@ -0,0 +1,37 @@
+//! Deleted notes aren't removed right away, they are kept in the trash (with `deleted_at` set)
+//! until either removed permanently from here, or purged by [`crate::DbThread`] once the retention runs out.
+//! Only the owner of a note sees it here.
+
+use crate::*;
+
+#[inline]
+#[get("/trash")]
+async fn list_trash(state: Data::<Server>, user: User) -> impl Responder {
+    HttpResponse::Ok().json(state.trashed_notes(user))
+}
+
+#[inline]
+#[post("/trash/{uuid}/restore")]
+async fn restore_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_any_note_as(user, &uuid, Access::Owner)?;
+    let note = state.restore_note(&uuid).ok_or(ApiError::NoteNotFound)?;
+    Ok(HttpResponse::Ok().json(note))
+}
+
+#[inline]
+#[delete("/trash/{uuid}")]
+async fn remove_note(state: Data::<Server>, user: User, uuid: web::Path::<Uuid>) -> ApiResult {
+    state.get_any_note_as(user, &uuid, Access::Owner)?;
+    if state.remove_note(&uuid) {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::NoteNotFound)
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_trash)
+        .service(restore_note)
+        .service(remove_note);
+}
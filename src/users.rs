@@ -0,0 +1,145 @@
+//! User accounts. Every note has an owner, and is private to them unless shared, see [`crate::sharing`].
+//! Accounts are made from the machine running the server, the first one takes over the notes, notebooks and
+//! templates made before there were any.
+//!
+//! Passwords are stored as Argon2id hashes in PHC strings, each with a salt of its own.
+
+use crate::*;
+
+use std::future::{ready, Ready};
+
+use actix_web::{HttpMessage, FromRequest, dev::Payload};
+use argon2::{
+    Argon2,
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng}
+};
+
+/// Hashed in place of the password of users that don't exist, see [`verify`].
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRzb21lc2FsdA$AbbpG8g9qQZOmXCsPNn5nBGdcGbqN9EoEb8mK7qO0yg";
+
+/// The user a request was made by, set by [`auth::authenticate`].
+#[derive(Clone, Copy, PartialEq)]
+pub struct User(pub Uuid);
+
+impl FromRequest for User {
+    type Error = ApiError;
+    type Future = Ready::<Result::<Self, ApiError>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<auth::Session>().map(|session| session.user).ok_or(ApiError::Unauthorized("not logged in")))
+    }
+}
+
+#[derive(Serialize)]
+struct Account {
+    id: Uuid,
+    name: Box::<str>
+}
+
+#[derive(Deserialize)]
+struct NewAccount {
+    name: Box::<str>,
+    password: Box::<str>
+}
+
+fn hash_password(password: &str) -> Result::<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|password_hash| {
+        Argon2::default().verify_password(password.as_bytes(), &password_hash).is_ok()
+    })
+}
+
+/// Checks the password of the user called `name`, returns their ID.
+pub async fn verify(state: &Server, name: &str, password: Box::<str>) -> Result::<Uuid, ApiError> {
+    let user = match state.pool.get()?.query_row(
+        "SELECT id, password_hash FROM users WHERE name = ?1",
+        params![name],
+        |row| Ok((Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"), row.get::<_, String>(1)?))
+    ) {
+        Ok(user) => Some(user),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e.into())
+    };
+    // hashing anyway when there's no such user, so that it takes just as long as a wrong password
+    let (id, password_hash) = user.clone().unwrap_or_else(|| (Uuid::nil(), DUMMY_PASSWORD_HASH.to_owned()));
+    let valid = web::block(move || verify_password(&password, &password_hash)).await.map_err(|e| ApiError::Internal(e.to_string()))?;
+    if !valid || user.is_none() { return Err(ApiError::Unauthorized("wrong user name or password")) }
+    Ok(id)
+}
+
+/// Makes a new account, only from the machine running the server.
+#[post("/users")]
+pub async fn create_user(state: Data::<Server>, req: HttpRequest, json: Json::<NewAccount>) -> ApiResult {
+    if !auth::is_host(&req) { return Err(ApiError::Forbidden(auth::HOST_ONLY)) }
+    let NewAccount { mut name, password } = json.into_inner();
+    validate::user_name(&mut name, &state.config)?;
+    validate::password(&password)?;
+
+    let account = Account { id: Uuid::new_v4(), name };
+    let password_hash = web::block(move || hash_password(&password))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .map_err(|e| ApiError::Internal(format!("could not hash password: {e}")))?;
+    let mut conn = state.pool.get()?;
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    let first = tx.query_row("SELECT NOT EXISTS (SELECT 1 FROM users)", [], |row| row.get::<_, bool>(0))?;
+    tx.execute(
+        "INSERT INTO users (id, name, password_hash, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![account.id.to_string(), account.name, password_hash, unix_now()]
+    ).map_err(|e| ApiError::from_unique(e, "user", &account.name))?;
+    if first {
+        tx.execute("UPDATE notebooks SET owner = ?1 WHERE owner IS NULL", params![account.id.to_string()])?;
+        tx.execute("UPDATE templates SET owner = ?1 WHERE owner IS NULL", params![account.id.to_string()])?;
+    }
+    tx.commit()?;
+    if first {
+        state.modify_notes_where(|note| note.owner.is_none(), |note| note.owner = Some(account.id));
+    }
+
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, format!("/api/v1/users/{id}", id = account.id)))
+        .json(account))
+}
+
+/// Every account, for picking who to share with.
+#[get("/users")]
+async fn list_users(state: Data::<Server>) -> ApiResult {
+    let conn = state.pool.get()?;
+    let users = conn.prepare("SELECT id, name FROM users ORDER BY name")?.query_map([], |row| {
+        Ok(Account {
+            id: Uuid::parse_str(&row.get::<_, String>(0)?).expect("invalid UUID"),
+            name: row.get(1)?
+        })
+    })?.collect::<Result::<Vec::<_>>>()?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[get("/users/me")]
+async fn get_me(state: Data::<Server>, user: User) -> ApiResult {
+    let name = state.pool.get()?.query_row(
+        "SELECT name FROM users WHERE id = ?1",
+        params![user.0.to_string()],
+        |row| row.get::<_, Box::<str>>(0)
+    )?;
+    Ok(HttpResponse::Ok().json(Account { id: user.0, name }))
+}
+
+/// Whether there's an account with this ID.
+pub fn exists(state: &Server, id: &Uuid) -> Result::<bool, ApiError> {
+    Ok(state.pool.get()?.query_row(
+        "SELECT EXISTS (SELECT 1 FROM users WHERE id = ?1)",
+        params![id.to_string()],
+        |row| row.get(0)
+    )?)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(list_users)
+        .service(get_me);
+}
@@ -0,0 +1,170 @@
+use serde::Serialize;
+
+use crate::{json, Note, UnixTimeStamp};
+use crate::api::ApiError;
+use crate::config::Config;
+
+/// 9999-12-31T23:59:59Z, the last second of the last year with four digits.
+const MAX_TIMESTAMP: UnixTimeStamp = 253402300799;
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String
+}
+
+/// Collects every failing field of a request instead of bailing out at the first one.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec::<FieldError>
+}
+
+/// Strips control characters, keeping newlines and tabs only if `multiline` is set.
+pub fn strip_control_chars(s: &str, multiline: bool) -> Box::<str> {
+    if !s.chars().any(char::is_control) { return s.into() }
+    s.chars().filter(|&c| {
+        !c.is_control() || (multiline && matches!(c, '\n' | '\t'))
+    }).collect()
+}
+
+impl Validator {
+    #[inline(always)]
+    pub fn error(&mut self, field: &'static str, message: impl Into::<String>) {
+        self.errors.push(FieldError { field, message: message.into() })
+    }
+
+    pub fn text(&mut self, field: &'static str, value: &mut Box::<str>, max_len: usize, multiline: bool) {
+        *value = strip_control_chars(value, multiline);
+        let len = value.chars().count();
+        if len > max_len {
+            self.error(field, format!("must be at most {max_len} characters long, got {len}"))
+        }
+    }
+
+    #[inline]
+    pub fn title(&mut self, value: &mut Box::<str>, config: &Config) {
+        self.text("title", value, config.max_title_len, false);
+        if value.trim().is_empty() {
+            self.error("title", "must not be empty")
+        }
+    }
+
+    #[inline]
+    pub fn description(&mut self, value: &mut Box::<str>, config: &Config) {
+        self.text("description", value, config.max_description_len, true)
+    }
+
+    /// Names are trimmed, and must be a non-empty single line.
+    pub fn name(&mut self, field: &'static str, value: &mut Box::<str>, config: &Config) {
+        self.text(field, value, config.max_tag_len, false);
+        if value.trim().len() != value.len() { *value = value.trim().into() }
+        if value.is_empty() {
+            self.error(field, "must not be empty")
+        }
+    }
+
+    /// Times have to fall between 1970 and 9999, so that date math on them can't overflow.
+    pub fn time(&mut self, field: &'static str, value: Option::<UnixTimeStamp>) {
+        if let Some(time) = value.filter(|time| !(0..=MAX_TIMESTAMP).contains(time)) {
+            self.error(field, format!("must be between 0 and {MAX_TIMESTAMP} (1970 to 9999), got {time}"))
+        }
+    }
+
+    /// Validates every tag, then sorts them and drops duplicates.
+    pub fn tags(&mut self, tags: &mut Vec::<Box::<str>>, config: &Config) {
+        tags.iter_mut().for_each(|tag| self.name("tags", tag, config));
+        tags.sort_unstable();
+        tags.dedup();
+        if tags.len() > config.max_tags {
+            self.error("tags", format!("must have at most {max} tags, got {len}", max = config.max_tags, len = tags.len()))
+        }
+    }
+
+    #[inline]
+    pub fn finish(self) -> Result::<(), Vec::<FieldError>> {
+        if self.errors.is_empty() { Ok(()) } else { Err(self.errors) }
+    }
+}
+
+#[inline]
+pub fn tag(name: &mut Box::<str>, config: &Config) -> Result::<(), ApiError> {
+    let mut v = Validator::default();
+    v.name("name", name, config);
+    v.finish().map_err(ApiError::Validation)
+}
+
+#[inline]
+pub fn notebook_name(name: &mut Box::<str>, config: &Config) -> Result::<(), ApiError> {
+    tag(name, config)
+}
+
+#[inline]
+pub fn device_name(name: &mut Box::<str>, config: &Config) -> Result::<(), ApiError> {
+    tag(name, config)
+}
+
+pub trait Validate {
+    fn validate_with(&mut self, v: &mut Validator, config: &Config);
+
+    #[inline]
+    fn validate(&mut self, config: &Config) -> Result::<(), ApiError> {
+        let mut v = Validator::default();
+        self.validate_with(&mut v, config);
+        v.finish().map_err(ApiError::Validation)
+    }
+}
+
+impl Validate for Note {
+    #[inline]
+    fn validate_with(&mut self, v: &mut Validator, config: &Config) {
+        v.title(&mut self.title, config);
+        v.description(&mut self.description, config);
+        v.tags(&mut self.tags, config);
+        v.time("due_at", self.due_at);
+        v.time("remind_at", self.remind_at)
+    }
+}
+
+impl Validate for json::Note {
+    #[inline]
+    fn validate_with(&mut self, v: &mut Validator, config: &Config) {
+        v.title(&mut self.title, config);
+        v.description(&mut self.description, config)
+    }
+}
+
+impl Validate for json::NoteBody {
+    #[inline]
+    fn validate_with(&mut self, v: &mut Validator, config: &Config) {
+        v.title(&mut self.title, config);
+        v.description(&mut self.description, config);
+        v.tags(&mut self.tags, config);
+        v.time("due_at", self.due_at);
+        v.time("remind_at", self.remind_at)
+    }
+}
+
+impl Validate for json::NotePatch {
+    #[inline]
+    fn validate_with(&mut self, v: &mut Validator, config: &Config) {
+        if let Some(ref mut title) = self.title { v.title(title, config) }
+        if let Some(ref mut description) = self.description { v.description(description, config) }
+        if let Some(ref mut tags) = self.tags { v.tags(tags, config) }
+        if let Some(due_at) = self.due_at { v.time("due_at", due_at) }
+        if let Some(remind_at) = self.remind_at { v.time("remind_at", remind_at) }
+    }
+}
+
+#[inline]
+pub fn user_name(name: &mut Box::<str>, config: &Config) -> Result::<(), ApiError> {
+    tag(name, config)
+}
+
+pub fn password(password: &str) -> Result::<(), ApiError> {
+    let mut v = Validator::default();
+    let len = password.chars().count();
+    if len < 8 {
+        v.error("password", format!("must be at least 8 characters long, got {len}"))
+    }
+    v.finish().map_err(ApiError::Validation)
+}
@@ -1,34 +1,208 @@
-const API_BASE_URL = "";
+const API_BASE_URL = "/api/v1";
 
 let debounceTimers = {};
+let statuses = [];
+let rawDescriptions = {};
+let overdueOnly = false;
+let me = null;
 
-window.addEventListener("load", async () => {
+function toDateTimeInput(timestamp) {
+  if (timestamp == null) return "";
+  const date = new Date(timestamp * 1000);
+  return new Date(date.getTime() - date.getTimezoneOffset() * 60000).toISOString().slice(0, 16);
+}
+
+function fromDateTimeInput(value) {
+  return value ? Math.floor(new Date(value).getTime() / 1000) : null;
+}
+
+function escapeHtml(text) {
+  const element = document.createElement("div");
+  element.textContent = text;
+  return element.innerHTML.replaceAll('"', "&quot;").replaceAll("'", "&#39;");
+}
+
+// opened from the QR code: the pairing token is traded for a device token (kept in a cookie) when logging in
+const pairingToken = new URLSearchParams(location.hash.slice(1)).get("pair");
+if (pairingToken) history.replaceState(null, "", location.pathname);
+
+async function logIn(user, password) {
+  const name = navigator.userAgentData?.platform || navigator.platform || "Browser";
+  const response = await fetch(`${API_BASE_URL}/pair`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ token: pairingToken, name, user, password }),
+  });
+  if (!response.ok) {
+    alert((await response.json()).message);
+    return;
+  }
+  location.reload();
+}
+
+document.getElementById("login-form").addEventListener("submit", (event) => {
+  event.preventDefault();
+  logIn(document.getElementById("login-user").value, document.getElementById("login-password").value);
+});
+
+// only works on the machine running the server
+document.getElementById("create-account").addEventListener("click", async () => {
+  const user = document.getElementById("login-user").value;
+  const password = document.getElementById("login-password").value;
+  const response = await fetch(`${API_BASE_URL}/users`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ name: user, password }),
+  });
+  if (!response.ok) {
+    const error = await response.json();
+    alert(error.fields?.map((field) => `${field.field} ${field.message}`).join("\n") || error.message);
+    return;
+  }
+  logIn(user, password);
+});
+
+document.getElementById("logout").addEventListener("click", async () => {
+  const response = await fetch(`${API_BASE_URL}/logout`, { method: "POST" });
+  if (response.ok) location.reload(); else console.error("failed to log out");
+});
+
+async function fetchMe() {
+  try {
+    const response = await fetch(`${API_BASE_URL}/users/me`);
+    if (response.status === 401) {
+      document.getElementById("login-form").hidden = false;
+      return;
+    }
+    if (!response.ok) throw new Error("failed to fetch user");
+    me = await response.json();
+    document.getElementById("account-name").textContent = `Logged in as ${me.name}`;
+    document.getElementById("account").hidden = false;
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function shareNote(uuid) {
+  const name = prompt("Share with user");
+  if (!name) return;
+  const permission = confirm(`Let ${name} edit the note too? Cancel to share it read-only.`) ? "write" : "read";
+  try {
+    const response = await fetch(`${API_BASE_URL}/users`);
+    if (!response.ok) throw new Error("failed to fetch users");
+    const user = (await response.json()).find((user) => user.name === name);
+    if (!user) {
+      alert(`There is no user called ${name}.`);
+      return;
+    }
+    const shared = await fetch(`${API_BASE_URL}/notes/${uuid}/shares/${user.id}`, {
+      method: "PUT",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ permission }),
+    });
+    if (!shared.ok) alert((await shared.json()).message);
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function linkNote(uuid) {
+  const days = prompt("Days until the public link expires, empty for never");
+  if (days === null) return;
+  const expires_at = days.trim() ? Math.floor(Date.now() / 1000) + Math.round(Number(days) * 86400) : null;
+  try {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}/share`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ expires_at }),
+    });
+    if (!response.ok) {
+      alert((await response.json()).message);
+      return;
+    }
+    // shown only this once, only its hash is kept
+    prompt("Public link, anyone who has it can read the note", (await response.json()).url);
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+// only the machine running the server gets one, every one carries a pairing token good for 5 minutes
+function fetchQrCode() {
   const qrcodeContainer = document.getElementById("qrcode-container");
   fetch("/qr.png")
     .then((response) => {
+      if (response.status === 403) {
+        qrcodeContainer.hidden = true;
+        return null;
+      }
       if (!response.ok) {
         throw new Error("Failed to fetch QR code");
       }
       return response.blob();
     })
     .then((blob) => {
+      if (!blob) return;
       const img = document.createElement("img");
       const span = document.createElement("span");
-      span.textContent = "QR code for your phone";
+      span.textContent = "Scan to pair your phone";
       img.src = URL.createObjectURL(blob);
       qrcodeContainer.innerHTML = "";
       qrcodeContainer.appendChild(img);
       qrcodeContainer.appendChild(span);
+      setTimeout(fetchQrCode, 4 * 60 * 1000);
     })
     .catch((error) => {
       qrcodeContainer.innerHTML = "<span>Error loading QR Code</span>";
       console.error(error);
     });
-});
+}
+
+window.addEventListener("load", fetchQrCode);
+
+async function fetchDevices() {
+  try {
+    const response = await fetch(`${API_BASE_URL}/devices`);
+    if (!response.ok) throw new Error("failed to fetch devices");
+    const devices = await response.json();
+    const devicesContainer = document.getElementById("devices");
+    devicesContainer.innerHTML = devices.length ? "" : "<span>No paired devices</span>";
+    devices.forEach((device) => {
+      const element = document.createElement("div");
+      element.className = "device";
+      element.innerHTML = `
+        <span>${escapeHtml(device.name)}${device.current ? " (this device)" : ""}, paired ${new Date(device.created_at * 1000).toLocaleString()}</span>
+        <button type="button">Revoke</button>
+      `;
+      element.querySelector("button").addEventListener("click", async () => {
+        if (!confirm(`Revoke "${device.name}"? It will have to be paired again.`)) return;
+        const response = await fetch(`${API_BASE_URL}/devices/${device.id}`, { method: "DELETE" });
+        if (response.ok) fetchDevices(); else console.error("failed to revoke device");
+      });
+      devicesContainer.appendChild(element);
+    });
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function fetchStatuses() {
+  try {
+    const response = await fetch(`${API_BASE_URL}/statuses`);
+    if (!response.ok) throw new Error("failed to fetch statuses");
+    statuses = (await response.json()).statuses;
+  } catch (error) {
+    console.error(error);
+  }
+}
 
 async function fetchNotes() {
   try {
-    const response = await fetch(`${API_BASE_URL}/notes`);
+    const response = await fetch(`${API_BASE_URL}/notes${overdueOnly ? "?overdue=true" : ""}`);
+    if (response.status === 401) {
+      document.getElementById("notes").innerHTML = "<span>This device is not logged in. Unless this is the machine running the server, scan the QR code shown on it first.</span>";
+      return;
+    }
     if (!response.ok) throw new Error("failed to fetch notes");
     const notes = await response.json();
     displayNotes(notes);
@@ -40,31 +214,63 @@ async function fetchNotes() {
 function displayNotes(notes) {
   const notesContainer = document.getElementById("notes");
   notesContainer.innerHTML = "";
-  notes.sort((a, b) => b.mod_time - a.mod_time);
-  
+
   notes.forEach(note => {
     const noteElement = document.createElement("div");
-    noteElement.className = "note";
+    noteElement.className = note.pinned ? "note pinned" : "note";
+    const done = statuses.find((status) => status.name === note.status)?.done;
+    if (note.due_at != null && note.due_at * 1000 < Date.now() && !done) noteElement.classList.add("overdue");
     noteElement.setAttribute("uuid", note.uuid);
+    noteElement.draggable = true;
     
     noteElement.innerHTML = `
       <div class="note-header">
-        <div class="note-title" contenteditable="true">${note.title}</div>
+        <div class="note-title" contenteditable="true">${escapeHtml(note.title)}</div>
+        <button class="pin-btn" title="${note.pinned ? "Unpin" : "Pin"}" onclick="reorderNote('${note.uuid}', { pinned: ${!note.pinned}, index: 0 })">
+          <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" width="22" height="22">
+            <path d="M9 3h6l-1 6 4 4H6l4-4zM12 13v8" fill="${note.pinned ? "#007bff" : "none"}" stroke="#007bff" stroke-width="2" stroke-linejoin="round" />
+          </svg>
+        </button>
+        ${note.owner === me?.id ? `<button class="share-btn" title="Share" onclick="shareNote('${note.uuid}')">
+          <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" width="22" height="22">
+            <path d="M12 15V3M7 8l5-5 5 5M5 13v7h14v-7" fill="none" stroke="#007bff" stroke-width="2" stroke-linejoin="round" />
+          </svg>
+        </button>
+        <button class="share-btn" title="Public link" onclick="linkNote('${note.uuid}')">
+          <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" width="22" height="22">
+            <path d="M10 14a4 4 0 0 0 6 0l3-3a4 4 0 0 0-6-6l-1 1M14 10a4 4 0 0 0-6 0l-3 3a4 4 0 0 0 6 6l1-1" fill="none" stroke="#007bff" stroke-width="2" stroke-linecap="round" />
+          </svg>
+        </button>` : ""}
         <button class="delete-btn" onclick="removeNote('${note.uuid}')">
           <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" width="25" height="25">
             <path d="M18 6L6 18M6 6l12 12" fill="none" stroke="red" stroke-width="4" stroke-linecap="square" stroke-linejoin="round" />
           </svg>
         </button>
       </div>
-      <div class="note-description" contenteditable="true">${note.description}</div>
+      <div class="note-description" contenteditable="true"></div>
+      <details class="checklist">
+        <summary>Checklist ${note.checklist.total ? `${note.checklist.checked}/${note.checklist.total}` : ""}</summary>
+        <ul class="checklist-items"></ul>
+        <input type="text" class="checklist-new-item" placeholder="Add item">
+      </details>
+      <div class="note-dates">
+        <label>Due <input type="datetime-local" class="due-input" value="${toDateTimeInput(note.due_at)}"></label>
+        <label>Remind <input type="datetime-local" class="remind-input" value="${toDateTimeInput(note.remind_at)}"></label>
+        <label>Repeat <input type="text" class="recurrence-input" placeholder="e.g. weekly" value="${escapeHtml(note.recurrence ?? "")}"></label>
+      </div>
+      <details class="attachments">
+        <summary>Attachments</summary>
+        <ul class="attachments-list"></ul>
+        <input type="file" class="attachments-upload" multiple>
+      </details>
+      <details class="backlinks">
+        <summary>Linked from</summary>
+        <ul class="backlinks-list"></ul>
+      </details>
       <div class="status-container">
         <div class="note-status-dropdown">
-          <input type="text" value="${note.status}" class="status-input" placeholder="status" readonly/>
-          <ul class="note-status-options">
-            <li class="note-status-option" data-value="Active">Active</li>
-            <li class="note-status-option" data-value="Completed">Completed</li>
-            <li class="note-status-option" data-value="Archived">Archived</li>
-          </ul>
+          <input type="text" value="${escapeHtml(note.status)}" class="status-input" placeholder="status" readonly/>
+          <ul class="note-status-options"></ul>
         </div>
       </div>
     `;
@@ -73,6 +279,20 @@ function displayNotes(notes) {
     const descriptionElement = noteElement.querySelector('.note-description');
     const dropdown = noteElement.querySelector('.note-status-dropdown');
     const statusInput = dropdown.querySelector('.status-input');
+    const optionsList = dropdown.querySelector('.note-status-options');
+
+    statuses.forEach((status) => {
+      const option = document.createElement("li");
+      option.className = "note-status-option";
+      option.dataset.value = status.name;
+      option.textContent = status.name;
+      option.style.color = status.color;
+      optionsList.appendChild(option);
+    });
+
+    const statusColor = statuses.find((status) => status.name === note.status)?.color;
+    if (statusColor) statusInput.style.color = statusColor;
+
     const listOfOptions = dropdown.querySelectorAll('.note-status-option');
 
     titleElement.setAttribute('data-placeholder', 'Title');
@@ -86,7 +306,8 @@ function displayNotes(notes) {
     const selectOption = (event) => {
       const selectedValue = event.currentTarget.textContent;
       statusInput.value = selectedValue;
-      updateNote(note.uuid);
+      // completing a recurring note spawns its next occurrence
+      updateNote(note.uuid).then(() => { if (note.recurrence) fetchNotes(); });
     };
 
     const closeDropdownFromOutside = () => {
@@ -103,15 +324,197 @@ function displayNotes(notes) {
 
     dropdown.addEventListener('click', toggleDropdown);
 
+    // the rendered markdown is shown until the description gets focused for editing
+    rawDescriptions[note.uuid] = note.description;
+    descriptionElement.innerHTML = note.description_html;
+    descriptionElement.addEventListener('focus', () => {
+      descriptionElement.textContent = rawDescriptions[note.uuid];
+      startCollab(note.uuid, descriptionElement);
+    });
+    descriptionElement.addEventListener('input', () => {
+      rawDescriptions[note.uuid] = descriptionElement.textContent;
+      collabSessions[note.uuid]?.send();
+    });
+    descriptionElement.addEventListener('blur', () => stopCollab(note.uuid));
+
     [titleElement, descriptionElement].forEach(element => {
       element.addEventListener('blur', () => debouncedUpdateNote(note.uuid));
       element.addEventListener('input', () => debouncedUpdateNote(note.uuid));
     });
 
+    const checklist = noteElement.querySelector('.checklist');
+    checklist.addEventListener('toggle', () => {
+      if (checklist.open) fetchChecklist(note.uuid, checklist);
+    });
+    checklist.querySelector('.checklist-new-item').addEventListener('keydown', async (event) => {
+      if (event.key !== "Enter" || !event.target.value.trim()) return;
+      await checklistRequest(note.uuid, "", "POST", { text: event.target.value });
+      event.target.value = "";
+      fetchChecklist(note.uuid, checklist);
+    });
+
+    noteElement.querySelectorAll('.note-dates input').forEach((input) => {
+      input.addEventListener('change', () => updateNoteDates(note.uuid, noteElement));
+    });
+
+    const attachments = noteElement.querySelector('.attachments');
+    attachments.addEventListener('toggle', () => {
+      if (attachments.open) fetchAttachments(note.uuid, attachments);
+    });
+    attachments.querySelector('.attachments-upload').addEventListener('change', async (event) => {
+      const form = new FormData();
+      [...event.target.files].forEach((file) => form.append("file", file));
+      try {
+        const response = await fetch(`${API_BASE_URL}/notes/${note.uuid}/attachments`, { method: "POST", body: form });
+        if (!response.ok) throw new Error("Failed to upload attachments");
+      } catch (error) {
+        console.error(error);
+      }
+      event.target.value = "";
+      fetchAttachments(note.uuid, attachments);
+    });
+
+    const backlinks = noteElement.querySelector('.backlinks');
+    backlinks.addEventListener('toggle', () => {
+      if (backlinks.open) fetchBacklinks(note.uuid, backlinks);
+    });
+
+    noteElement.addEventListener("dragstart", (event) => {
+      event.dataTransfer.setData("text/plain", note.uuid);
+    });
+    noteElement.addEventListener("dragover", (event) => event.preventDefault());
+    noteElement.addEventListener("drop", (event) => {
+      event.preventDefault();
+      const uuid = event.dataTransfer.getData("text/plain");
+      if (uuid === note.uuid) return;
+      const group = notes.filter((n) => n.pinned === note.pinned && n.uuid !== uuid);
+      reorderNote(uuid, { pinned: note.pinned, index: group.findIndex((n) => n.uuid === note.uuid) });
+    });
+
     notesContainer.appendChild(noteElement);
   });
 }
 
+async function checklistRequest(uuid, path, method, body) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}/items${path}`, {
+      method,
+      headers: { "Content-Type": "application/json" },
+      body: body && JSON.stringify(body),
+    });
+    if (!response.ok) throw new Error("Failed to update checklist");
+    return response.status === 204 ? null : await response.json();
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function fetchChecklist(uuid, checklist) {
+  const items = await checklistRequest(uuid, "", "GET");
+  if (!items) return;
+
+  const list = checklist.querySelector('.checklist-items');
+  list.innerHTML = "";
+  items.forEach((item) => {
+    const itemElement = document.createElement("li");
+
+    const checkbox = document.createElement("input");
+    checkbox.type = "checkbox";
+    checkbox.checked = item.checked;
+    checkbox.addEventListener("change", async () => {
+      await checklistRequest(uuid, `/${item.id}`, "PATCH", { checked: checkbox.checked });
+      fetchChecklist(uuid, checklist);
+    });
+
+    const text = document.createElement("span");
+    text.textContent = item.text;
+
+    const remove = document.createElement("button");
+    remove.textContent = "×";
+    remove.addEventListener("click", async () => {
+      await checklistRequest(uuid, `/${item.id}`, "DELETE");
+      fetchChecklist(uuid, checklist);
+    });
+
+    itemElement.append(checkbox, text, remove);
+    list.appendChild(itemElement);
+  });
+
+  const checked = items.filter((item) => item.checked).length;
+  checklist.querySelector("summary").textContent = items.length ? `Checklist ${checked}/${items.length}` : "Checklist";
+}
+
+async function fetchAttachments(uuid, attachments) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}/attachments`);
+    if (!response.ok) throw new Error("Failed to fetch attachments");
+    const files = await response.json();
+    const list = attachments.querySelector('.attachments-list');
+    list.innerHTML = "";
+    files.forEach((file) => {
+      const itemElement = document.createElement("li");
+
+      const link = document.createElement("a");
+      link.href = `${API_BASE_URL}/attachments/${file.id}`;
+      link.target = "_blank";
+      link.textContent = file.file_name;
+      if (file.content_type === "image/jpeg" || file.content_type === "image/png") {
+        const thumbnail = document.createElement("img");
+        thumbnail.className = "attachment-thumbnail";
+        thumbnail.src = `${API_BASE_URL}/attachments/${file.id}/thumb?size=128`;
+        thumbnail.alt = "";
+        thumbnail.loading = "lazy";
+        link.prepend(thumbnail);
+      }
+
+      const remove = document.createElement("button");
+      remove.textContent = "×";
+      remove.addEventListener("click", async () => {
+        await fetch(`${API_BASE_URL}/attachments/${file.id}`, { method: "DELETE" });
+        fetchAttachments(uuid, attachments);
+      });
+
+      itemElement.append(link, remove);
+      list.appendChild(itemElement);
+    });
+    attachments.querySelector("summary").textContent = files.length ? `Attachments (${files.length})` : "Attachments";
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function fetchBacklinks(uuid, backlinks) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}/backlinks`);
+    if (!response.ok) throw new Error("Failed to fetch backlinks");
+    const sources = await response.json();
+    const list = backlinks.querySelector('.backlinks-list');
+    list.innerHTML = "";
+    sources.forEach((source) => {
+      const itemElement = document.createElement("li");
+      itemElement.textContent = source.title || "(untitled)";
+      list.appendChild(itemElement);
+    });
+    if (!sources.length) list.innerHTML = "<li>No other notes link here</li>";
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function reorderNote(uuid, { pinned, index }) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}/reorder`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ pinned, index }),
+    });
+    if (!response.ok) throw new Error("Failed to reorder note");
+  } catch (error) {
+    console.error(error);
+  }
+  fetchNotes();
+}
+
 function debouncedUpdateNote(uuid) {
   if (debounceTimers[uuid]) {
     clearTimeout(debounceTimers[uuid]);
@@ -125,16 +528,16 @@ function debouncedUpdateNote(uuid) {
 async function updateNote(uuid) {
   const noteElement = document.querySelector(`.note[uuid="${uuid}"]`);
   const updatedNote = {
-    uuid,
     title: noteElement.querySelector('.note-title').textContent,
-    description: noteElement.querySelector('.note-description').textContent,
+    // while editing together the description goes through the WebSocket instead
+    description: collabSessions[uuid] ? undefined : rawDescriptions[uuid],
     status: noteElement.querySelector('.status-input').value,
     mod_time: Math.floor(Date.now() / 1000)
   };
 
   try {
-    const response = await fetch(`${API_BASE_URL}/update-note`, {
-      method: "PUT",
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}`, {
+      method: "PATCH",
       headers: {
         "Content-Type": "application/json",
       },
@@ -142,6 +545,213 @@ async function updateNote(uuid) {
     });
     
     if (!response.ok) throw new Error("Failed to update note");
+    const note = await response.json();
+    const descriptionElement = noteElement.querySelector('.note-description');
+    if (document.activeElement !== descriptionElement) {
+      descriptionElement.innerHTML = note.description_html;
+    }
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+// Collaborative editing of descriptions, see `src/collab.rs` for the protocol. Operations count code points,
+// retaining (positive numbers), deleting (negative numbers) or inserting (strings).
+const otLength = (component) => typeof component === "string" ? [...component].length : Math.abs(component);
+
+function otPush(op, component) {
+  if (component === 0 || component === "") return op;
+  const last = op[op.length - 1];
+  if (typeof component === "string") {
+    if (typeof last === "string") op[op.length - 1] = last + component;
+    // inserts go before deletes
+    else if (last < 0 && typeof op[op.length - 2] === "string") op[op.length - 2] += component;
+    else if (last < 0) op.splice(op.length - 1, 0, component);
+    else op.push(component);
+  } else if (typeof last === "number" && Math.sign(last) === Math.sign(component)) {
+    op[op.length - 1] = last + component;
+  } else {
+    op.push(component);
+  }
+  return op;
+}
+
+function otDiff(oldText, newText) {
+  const a = [...oldText], b = [...newText];
+  let prefix = 0, suffix = 0;
+  while (prefix < a.length && prefix < b.length && a[prefix] === b[prefix]) prefix++;
+  while (suffix < a.length - prefix && suffix < b.length - prefix && a[a.length - 1 - suffix] === b[b.length - 1 - suffix]) suffix++;
+  const op = [];
+  otPush(op, prefix);
+  otPush(op, b.slice(prefix, b.length - suffix).join(""));
+  otPush(op, -(a.length - prefix - suffix));
+  otPush(op, suffix);
+  return op;
+}
+
+function otApply(op, text) {
+  const chars = [...text];
+  let index = 0, out = "";
+  op.forEach((component) => {
+    if (typeof component === "string") {
+      out += component;
+    } else if (component > 0) {
+      out += chars.slice(index, index + component).join("");
+      index += component;
+    } else {
+      index -= component;
+    }
+  });
+  return out;
+}
+
+// returns [a', b'] so that applying b' after a equals applying a' after b, with a's inserts going first
+function otTransform(a, b) {
+  const aPrime = [], bPrime = [];
+  let i = 0, j = 0, x = a[i++], y = b[j++];
+  while (x !== undefined || y !== undefined) {
+    if (typeof x === "string") {
+      otPush(aPrime, x);
+      otPush(bPrime, otLength(x));
+      x = a[i++];
+      continue;
+    }
+    if (typeof y === "string") {
+      otPush(aPrime, otLength(y));
+      otPush(bPrime, y);
+      y = b[j++];
+      continue;
+    }
+    if (x === undefined || y === undefined) throw new Error("operations have different lengths");
+    const n = Math.min(Math.abs(x), Math.abs(y));
+    if (x > 0 && y > 0) {
+      otPush(aPrime, n);
+      otPush(bPrime, n);
+    } else if (x < 0 && y > 0) {
+      otPush(aPrime, -n);
+    } else if (x > 0 && y < 0) {
+      otPush(bPrime, -n);
+    }
+    x = Math.abs(x) > n ? x - Math.sign(x) * n : a[i++];
+    y = Math.abs(y) > n ? y - Math.sign(y) * n : b[j++];
+  }
+  return [aPrime, bPrime];
+}
+
+function otTransformIndex(op, index) {
+  let oldIndex = 0, newIndex = index;
+  for (const component of op) {
+    if (typeof component === "string") {
+      newIndex += otLength(component);
+    } else if (component > 0) {
+      oldIndex += component;
+    } else {
+      newIndex -= Math.min(index, oldIndex - component) - oldIndex;
+      oldIndex -= component;
+    }
+    if (oldIndex > index) break;
+  }
+  return newIndex;
+}
+
+const collabSessions = {};
+
+function startCollab(uuid, element) {
+  if (collabSessions[uuid]) {
+    collabSessions[uuid].closing = false;
+    return;
+  }
+  const protocol = location.protocol === "https:" ? "wss:" : "ws:";
+  const socket = new WebSocket(`${protocol}//${location.host}${API_BASE_URL}/notes/${uuid}/ws`);
+  // `shadow` is the text the server will have once `pending` is acknowledged, local edits are diffed against it
+  const collab = { socket, rev: null, pending: null, shadow: rawDescriptions[uuid], closing: false };
+  collabSessions[uuid] = collab;
+
+  const applyLocally = (op) => {
+    const focused = document.activeElement === element;
+    const caret = focused ? caretOffset(element) : null;
+    rawDescriptions[uuid] = otApply(op, rawDescriptions[uuid]);
+    if (!focused) return;
+    element.textContent = rawDescriptions[uuid];
+    if (caret !== null) setCaret(element, otTransformIndex(op, caret));
+  };
+
+  collab.send = () => {
+    if (collab.pending || collab.rev === null || socket.readyState !== WebSocket.OPEN) return;
+    const op = otDiff(collab.shadow, rawDescriptions[uuid]);
+    if (op.every((component) => typeof component === "number" && component > 0)) return;
+    collab.pending = op;
+    collab.shadow = rawDescriptions[uuid];
+    socket.send(JSON.stringify({ rev: collab.rev, ops: op }));
+  };
+
+  socket.addEventListener("message", (event) => {
+    const message = JSON.parse(event.data);
+    if (message.type === "init") {
+      // whatever changed on the server since the note was fetched is merged with what was typed meanwhile
+      const [, remote] = otTransform(otDiff(collab.shadow, rawDescriptions[uuid]), otDiff(collab.shadow, message.text));
+      collab.rev = message.rev;
+      collab.shadow = message.text;
+      applyLocally(remote);
+      collab.send();
+    } else if (message.type === "ack") {
+      collab.rev = message.rev;
+      collab.pending = null;
+      collab.send();
+      if (collab.closing && !collab.pending) socket.close();
+    } else if (message.type === "op") {
+      let op = message.ops;
+      collab.rev = message.rev;
+      if (collab.pending) [collab.pending, op] = otTransform(collab.pending, op);
+      const [, local] = otTransform(otDiff(collab.shadow, rawDescriptions[uuid]), op);
+      collab.shadow = otApply(op, collab.shadow);
+      applyLocally(local);
+    } else if (message.type === "error") {
+      console.error(message.message);
+      socket.close();
+    }
+  });
+  socket.addEventListener("close", () => {
+    if (collabSessions[uuid] === collab) delete collabSessions[uuid];
+  });
+}
+
+function stopCollab(uuid) {
+  const collab = collabSessions[uuid];
+  if (!collab) return;
+  collab.closing = true;
+  if (!collab.pending) collab.socket.close();
+}
+
+function caretOffset(element) {
+  const selection = window.getSelection();
+  if (!selection.rangeCount || !element.contains(selection.focusNode)) return null;
+  const range = document.createRange();
+  range.selectNodeContents(element);
+  range.setEnd(selection.focusNode, selection.focusOffset);
+  return [...range.toString()].length;
+}
+
+function setCaret(element, offset) {
+  const text = element.firstChild;
+  if (!text) return;
+  const units = [...element.textContent].slice(0, offset).join("").length;
+  window.getSelection().collapse(text, Math.min(units, text.length));
+}
+
+async function updateNoteDates(uuid, noteElement) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}`, {
+      method: "PATCH",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({
+        due_at: fromDateTimeInput(noteElement.querySelector('.due-input').value),
+        remind_at: fromDateTimeInput(noteElement.querySelector('.remind-input').value),
+        recurrence: noteElement.querySelector('.recurrence-input').value.trim() || null,
+      }),
+    });
+    if (!response.ok) throw new Error("Failed to update note dates");
+    fetchNotes();
   } catch (error) {
     console.error(error);
   }
@@ -151,15 +761,15 @@ document.getElementById("note-form").addEventListener("submit", async (e) => {
   e.preventDefault();
   const title = document.getElementById("title").value;
   const description = document.getElementById("description").value;
+  const template = document.getElementById("template").value;
   const note = {
     title,
     description,
-    status: "Active",
     mod_time: Math.floor(Date.now() / 1000),
   };
   
   try {
-    const response = await fetch(`${API_BASE_URL}/new-note`, {
+    const response = await fetch(`${API_BASE_URL}/notes${template ? `?template=${template}` : ""}`, {
       method: "POST",
       headers: {
         "Content-Type": "application/json",
@@ -168,30 +778,258 @@ document.getElementById("note-form").addEventListener("submit", async (e) => {
     });
     if (!response.ok) throw new Error("failed to add note");
     document.getElementById("note-form").reset();
+    toggleTemplate();
     fetchNotes();
   } catch (error) {
     console.error(error);
   }
 });
 
+async function fetchTemplates() {
+  try {
+    const response = await fetch(`${API_BASE_URL}/templates`);
+    if (!response.ok) throw new Error("failed to fetch templates");
+    const templates = await response.json();
+    const select = document.getElementById("template");
+    select.innerHTML = '<option value="">No template</option>';
+    templates.forEach((template) => {
+      const option = document.createElement("option");
+      option.value = template.uuid;
+      option.textContent = template.name;
+      select.appendChild(option);
+    });
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+// fields left empty are filled in from the template
+function toggleTemplate() {
+  const required = !document.getElementById("template").value;
+  document.getElementById("title").required = required;
+  document.getElementById("description").required = required;
+}
+
+document.getElementById("template").addEventListener("change", toggleTemplate);
+
+document.getElementById("save-template").addEventListener("click", async () => {
+  const name = prompt("Template name");
+  if (!name) return;
+  try {
+    const response = await fetch(`${API_BASE_URL}/templates`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({
+        name,
+        title: document.getElementById("title").value,
+        description: document.getElementById("description").value,
+      }),
+    });
+    if (!response.ok) throw new Error("failed to save template");
+    fetchTemplates();
+  } catch (error) {
+    console.error(error);
+  }
+});
+
 async function removeNote(uuid) {
   try {
-    const response = await fetch(`${API_BASE_URL}/remove-note`, {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}`, {
       method: "DELETE",
-      headers: { "Content-Type": "application/json" },
-      body: JSON.stringify({ uuid: uuid })
     });
     if (!response.ok) throw new Error("Failed to remove note");
     const noteElement = document.querySelector(`.note[uuid="${uuid}"]`);
     if (noteElement) {
       noteElement.remove();
     }
+    fetchTrash();
   } catch (error) {
     console.error(error);
   }
 }
 
-fetchNotes();
+async function fetchTrash() {
+  try {
+    const response = await fetch(`${API_BASE_URL}/trash`);
+    if (!response.ok) throw new Error("failed to fetch trash");
+    const notes = await response.json();
+    displayTrash(notes);
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+function displayTrash(notes) {
+  const trashContainer = document.getElementById("trash");
+  trashContainer.innerHTML = "";
+  notes.sort((a, b) => b.deleted_at - a.deleted_at);
+
+  notes.forEach(note => {
+    const noteElement = document.createElement("div");
+    noteElement.className = "trashed-note";
+
+    const titleElement = document.createElement("span");
+    titleElement.className = "trashed-note-title";
+    titleElement.textContent = note.title;
+
+    const restoreButton = document.createElement("button");
+    restoreButton.textContent = "Restore";
+    restoreButton.addEventListener("click", () => restoreNote(note.uuid));
+
+    const removeButton = document.createElement("button");
+    removeButton.textContent = "Delete forever";
+    removeButton.addEventListener("click", () => removeNoteForever(note.uuid));
+
+    noteElement.append(titleElement, restoreButton, removeButton);
+    trashContainer.appendChild(noteElement);
+  });
+}
+
+async function restoreNote(uuid) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/trash/${uuid}/restore`, { method: "POST" });
+    if (!response.ok) throw new Error("Failed to restore note");
+    fetchNotes();
+    fetchTrash();
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function removeNoteForever(uuid) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/trash/${uuid}`, { method: "DELETE" });
+    if (!response.ok) throw new Error("Failed to remove note");
+    fetchTrash();
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+async function fetchBoard() {
+  try {
+    const response = await fetch(`${API_BASE_URL}/board`);
+    if (!response.ok) throw new Error("failed to fetch board");
+    const board = await response.json();
+    displayBoard(board.columns);
+  } catch (error) {
+    console.error(error);
+  }
+}
+
+function displayBoard(columns) {
+  const boardElement = document.getElementById("board");
+  boardElement.innerHTML = "";
+
+  columns.forEach(column => {
+    const columnElement = document.createElement("div");
+    columnElement.className = "board-column";
+    columnElement.style.borderTopColor = column.color;
+
+    const header = document.createElement("div");
+    header.className = "board-column-header";
+    header.textContent = `${column.status} (${column.notes.length})`;
+    columnElement.appendChild(header);
+
+    const cards = document.createElement("div");
+    cards.className = "board-cards";
+    column.notes.forEach(note => {
+      const card = document.createElement("div");
+      card.className = "board-card";
+      card.draggable = true;
+      card.textContent = note.title;
+      card.addEventListener("dragstart", (event) => {
+        event.dataTransfer.setData("text/plain", note.uuid);
+      });
+      cards.appendChild(card);
+    });
+    columnElement.appendChild(cards);
+
+    columnElement.addEventListener("dragover", (event) => event.preventDefault());
+    columnElement.addEventListener("drop", (event) => {
+      event.preventDefault();
+      const uuid = event.dataTransfer.getData("text/plain");
+      const index = Array.from(cards.children).filter((card) => {
+        const rect = card.getBoundingClientRect();
+        return rect.top + rect.height / 2 < event.clientY;
+      }).length;
+      moveNote(uuid, column.status, index);
+    });
+
+    boardElement.appendChild(columnElement);
+  });
+}
+
+async function moveNote(uuid, status, index) {
+  try {
+    const response = await fetch(`${API_BASE_URL}/notes/${uuid}/move`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ status, index }),
+    });
+    if (!response.ok) throw new Error("Failed to move note");
+  } catch (error) {
+    console.error(error);
+  }
+  fetchBoard();
+}
+
+document.querySelectorAll("#view-switch button").forEach((button) => {
+  button.addEventListener("click", () => {
+    const board = button.dataset.view === "board";
+    document.querySelectorAll("#view-switch button").forEach((b) => b.classList.toggle("selected", b === button));
+    document.getElementById("board").hidden = !board;
+    document.getElementById("notes-container").hidden = board;
+    if (board) fetchBoard(); else fetchNotes();
+  });
+});
+
+document.querySelector("#overdue-filter input").addEventListener("change", (event) => {
+  overdueOnly = event.target.checked;
+  fetchNotes();
+});
+
+function showReminder(reminder) {
+  const title = reminder.title || "(untitled)";
+  if ("Notification" in window && Notification.permission === "granted") {
+    new Notification("Reminder", { body: title });
+  }
+  const element = document.createElement("div");
+  element.className = "reminder";
+  element.textContent = `Reminder: ${title}`;
+  element.addEventListener("click", () => element.remove());
+  document.getElementById("reminders").appendChild(element);
+}
+
+document.body.addEventListener("click", () => {
+  if ("Notification" in window && Notification.permission === "default") Notification.requestPermission();
+}, { once: true });
+
+new EventSource(`${API_BASE_URL}/reminders/events`).addEventListener("reminder", (event) => {
+  showReminder(JSON.parse(event.data));
+});
+
+// changes made on other devices (and our own echoed back), applied in one go and never under someone typing
+let refreshTimer = null;
+function scheduleRefresh() {
+  clearTimeout(refreshTimer);
+  refreshTimer = setTimeout(() => {
+    if (document.activeElement?.closest(".note")) {
+      document.activeElement.addEventListener("blur", scheduleRefresh, { once: true });
+      return;
+    }
+    if (document.getElementById("board").hidden) fetchNotes(); else fetchBoard();
+    fetchTrash();
+  }, 300);
+}
+
+const changes = new EventSource(`${API_BASE_URL}/events`);
+["created", "updated", "deleted", "reset"].forEach((type) => changes.addEventListener(type, scheduleRefresh));
+
+Promise.all([fetchMe(), fetchStatuses()]).then(fetchNotes);
+fetchTemplates();
+fetchTrash();
+fetchDevices();
 
 function setupCustomDropdown() {
   const statusContainers = document.querySelectorAll('.status-container');